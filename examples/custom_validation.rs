@@ -0,0 +1,38 @@
+// Check the Basic credentials in the handler itself, here against a
+// hard-coded token standing for an external service, instead of against an
+// Htpasswd file.
+//
+// Run with `cargo run --example custom_validation`, then try
+// `curl -u alice:let-me-in http://127.0.0.1:8080/`
+
+use actix_web::{
+    web,
+    App,
+    HttpResponse,
+    HttpServer,
+};
+use actix_htpasswd::BasicCredentials;
+
+// Stand-in for a call to an external authentication service
+async fn check_with_service(credentials: &BasicCredentials) -> bool {
+    credentials.expose_password() == "let-me-in"
+}
+
+async fn index(credentials: BasicCredentials) -> HttpResponse {
+    if check_with_service(&credentials).await {
+        HttpResponse::Ok().body(format!("Welcome, {}", credentials.user))
+    } else {
+        HttpResponse::Forbidden().body("Rejected by the authentication service")
+    }
+}
+
+#[actix_rt::main]
+async fn main() -> std::io::Result<()> {
+    HttpServer::new(|| {
+        App::new()
+            .route("/", web::get().to(index))
+    })
+        .bind("127.0.0.1:8080")?
+        .run()
+        .await
+}
@@ -0,0 +1,59 @@
+// Toy custom scheme: "{REV}" followed by the reversed password. Obviously not
+// something to use for real, but enough to show how a bespoke format plugs in.
+//
+// Run with `cargo run --example custom_verifier`, then try
+// `curl -u alice:secret http://127.0.0.1:8080/`
+
+use std::{
+    fs,
+    env,
+};
+
+use actix_web::{
+    web,
+    App,
+    HttpServer,
+};
+use actix_htpasswd::{
+    user_control_policy::AnyLoggedUser,
+    AuthControl,
+    CustomVerifier,
+    HtpasswdDatabase,
+    LoadOptions,
+};
+
+struct ReversedVerifier;
+impl CustomVerifier for ReversedVerifier {
+    fn prefix(&self) -> &str {
+        "{REV}"
+    }
+
+    fn verify(&self, stored: &str, candidate: &str) -> bool {
+        stored[self.prefix().len()..].chars().eq(candidate.chars().rev())
+    }
+}
+
+async fn index(auth_control: AuthControl<AnyLoggedUser>) -> String {
+    format!("{}", auth_control)
+}
+
+#[actix_rt::main]
+async fn main() -> std::io::Result<()> {
+    let htpasswd_file_path = env::temp_dir().join("custom_verifier.htpasswd");
+    fs::write(&htpasswd_file_path, "alice:{REV}terces\n")?;
+
+    let options = LoadOptions::new().custom_verifier(ReversedVerifier);
+    let htpasswd_database = web::Data::new(
+        HtpasswdDatabase::load(&htpasswd_file_path, &options)
+            .expect("Cannot load the Htpasswd file")
+    );
+
+    HttpServer::new(move || {
+        App::new()
+            .app_data(htpasswd_database.clone())
+            .route("/", web::get().to(index))
+    })
+        .bind("127.0.0.1:8080")?
+        .run()
+        .await
+}
@@ -0,0 +1,61 @@
+// Two Htpasswd databases in the same app, told apart by their newtype: the
+// admins guard "/admin", and the API clients guard "/api".
+//
+// Run with `cargo run --example multiple_databases`, then try
+// `curl -u root:admin-secret http://127.0.0.1:8080/admin` and
+// `curl -u client:api-secret http://127.0.0.1:8080/api`
+
+use actix_web::{
+    web,
+    App,
+    HttpServer,
+};
+use actix_htpasswd::{
+    user_control_policy::AnyLoggedUser,
+    AuthControlIn,
+    HtpasswdDatabase,
+};
+
+struct AdminDb(HtpasswdDatabase);
+impl AsRef<HtpasswdDatabase> for AdminDb {
+    fn as_ref(&self) -> &HtpasswdDatabase {
+        &self.0
+    }
+}
+
+struct ApiDb(HtpasswdDatabase);
+impl AsRef<HtpasswdDatabase> for ApiDb {
+    fn as_ref(&self) -> &HtpasswdDatabase {
+        &self.0
+    }
+}
+
+async fn admin(auth_control: AuthControlIn<AnyLoggedUser, AdminDb>) -> String {
+    format!("Admin area, {}", auth_control.auth_result)
+}
+
+async fn api(auth_control: AuthControlIn<AnyLoggedUser, ApiDb>) -> String {
+    format!("API, {}", auth_control.auth_result)
+}
+
+#[actix_rt::main]
+async fn main() -> std::io::Result<()> {
+    let mut admins = HtpasswdDatabase::new();
+    admins.add("root", "admin-secret").expect("Cannot add admin");
+    let admin_db = web::Data::new(AdminDb(admins));
+
+    let mut clients = HtpasswdDatabase::new();
+    clients.add("client", "api-secret").expect("Cannot add API client");
+    let api_db = web::Data::new(ApiDb(clients));
+
+    HttpServer::new(move || {
+        App::new()
+            .app_data(admin_db.clone())
+            .app_data(api_db.clone())
+            .route("/admin", web::get().to(admin))
+            .route("/api", web::get().to(api))
+    })
+        .bind("127.0.0.1:8080")?
+        .run()
+        .await
+}
@@ -1,26 +1,29 @@
 use std::{
     fmt::{
+        Debug,
         Display,
         Formatter,
     },
-    marker::PhantomData
+    marker::PhantomData,
+    sync::Arc,
 };
 
 use actix_web::{
     FromRequest,
     HttpRequest,
+    HttpResponse,
+    Responder,
     dev::Payload,
-    web::Data,
-    error::{
-        Error as HttpError,
-        ErrorForbidden,
-        ErrorUnauthorized
+    web::{
+        self,
+        Data,
     },
 };
 use futures::future::{
-    err,
     ok,
-    Ready
+    FutureExt,
+    LocalBoxFuture,
+    Ready,
 };
 use serde::{
     Serialize,
@@ -28,9 +31,29 @@ use serde::{
 };
 
 use crate::{
-    auth_data::AuthData,
+    auth_data::{
+        AuthData,
+        CredentialStatus,
+    },
+    auth_error::AuthError,
+    auth_stats::AuthStats,
+    basic_auth_config::BasicAuthConfig,
+    error::Error,
+    hash_lookup::HashLookup,
     htpasswd_database::HtpasswdDatabase,
+    ip_rate_limiter::IpRateLimiter,
+    login_tracker::LoginTracker,
+    merged_reloadable_database::MergedReloadableDatabase,
+    password_hash::{
+        self,
+        PasswordHash
+    },
+    response_map::{
+        FailureKind,
+        ResponseMap,
+    },
     user_control_policy::UserControlPolicy,
+    verification_limiter::VerificationLimiter,
 };
 
 #[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
@@ -48,6 +71,28 @@ impl Display for AuthResult {
     }
 }
 
+// Shape of the JSON body of an AuthResult, e.g. for a `/whoami` endpoint:
+// `{"authenticated":true,"user":"alice"}` or `{"authenticated":false}`
+#[derive(Serialize)]
+struct Identity<'a> {
+    authenticated: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user: Option<&'a str>,
+}
+
+impl Responder for AuthResult {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<HttpResponse, actix_web::Error>>;
+
+    fn respond_to(self, _: &HttpRequest) -> Self::Future {
+        let identity = match &self {
+            AuthResult::Anonymous => Identity { authenticated: false, user: None },
+            AuthResult::LoggedUser { user } => Identity { authenticated: true, user: Some(user) },
+        };
+        ok(HttpResponse::Ok().json(identity))
+    }
+}
+
 #[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
 pub struct AuthControl<U: UserControlPolicy> {
     _phantom_data: PhantomData<U>, // keep UserControlPolicy type
@@ -55,51 +100,372 @@ pub struct AuthControl<U: UserControlPolicy> {
 }
 
 impl<U: UserControlPolicy> FromRequest for AuthControl<U> {
-    type Error = HttpError;
-    type Future = Ready<Result<Self, HttpError>>;
-    type Config = ();
+    type Error = AuthError;
+    type Future = LocalBoxFuture<'static, Result<Self, AuthError>>;
+    type Config = BasicAuthConfig;
 
     fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
-        let htpasswd_database = req.app_data::<Data<HtpasswdDatabase>>()
-            .expect("No HtpasswdDatabase added to the actix app. Cannot check credentials");
-
-        // Extract authentication data from the request, and match it against
-        // the HtpasswdDatabase carried by the Actix app
-        let auth_result = match AuthData::from_request(req) {
-            Ok(Some(auth_data)) => {
-                if htpasswd_database.is_valid(&auth_data) {
-                    AuthResult::LoggedUser {
-                        user: auth_data.user,
-                    }
-                } else {
-                    return err(ErrorUnauthorized(format!(
-                        "Unknown user or invalid password"
-                    )));
+        let credential_source = if let Some(hash_lookup) = app_data::<HashLookup>(req) {
+            CredentialSource::Lookup(hash_lookup)
+        } else if let Some(merged) = req.app_data::<Data<MergedReloadableDatabase>>() {
+            CredentialSource::Merged(merged.clone())
+        } else {
+            CredentialSource::Database(
+                req.app_data::<Data<HtpasswdDatabase>>()
+                    .expect("No HtpasswdDatabase, MergedReloadableDatabase nor HashLookup added to the actix app. Cannot check credentials")
+                    .clone()
+            )
+        };
+
+        check_request::<U>(req, credential_source)
+            .map(|res| res.map(|auth_result| AuthControl {
+                _phantom_data: PhantomData,
+                auth_result,
+            }))
+            .boxed_local()
+    }
+}
+
+// Same as `AuthControl`, but checks the credentials against the `Data<Db>`
+// of the app, where `Db` is a newtype around an HtpasswdDatabase. Several
+// databases can then guard different scopes of the same app, each picked
+// by its type, e.g. `AuthControlIn<AnyLoggedUser, AdminDb>` next to
+// `AuthControlIn<AnyLoggedUser, ApiDb>` (see the `multiple_databases`
+// example). A `HashLookup` or `MergedReloadableDatabase` of the app is not
+// used there.
+pub struct AuthControlIn<U: UserControlPolicy, Db> {
+    _phantom_data: PhantomData<(U, Db)>, // keep UserControlPolicy and database types
+    pub auth_result: AuthResult,
+}
+
+impl<U: UserControlPolicy, Db: AsRef<HtpasswdDatabase> + 'static> FromRequest for AuthControlIn<U, Db> {
+    type Error = AuthError;
+    type Future = LocalBoxFuture<'static, Result<Self, AuthError>>;
+    type Config = BasicAuthConfig;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let htpasswd_database = req.app_data::<Data<Db>>()
+            .unwrap_or_else(|| panic!(
+                "No Data<{}> added to the actix app. Cannot check credentials",
+                std::any::type_name::<Db>()
+            ))
+            .clone()
+            .into_inner();
+
+        check_request::<U>(req, CredentialSource::Typed(htpasswd_database))
+            .map(|res| res.map(|auth_result| AuthControlIn {
+                _phantom_data: PhantomData,
+                auth_result,
+            }))
+            .boxed_local()
+    }
+}
+
+impl<U: UserControlPolicy, Db> Debug for AuthControlIn<U, Db> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        f.debug_struct("AuthControlIn")
+            .field("auth_result", &self.auth_result)
+            .finish()
+    }
+}
+
+impl<U: UserControlPolicy, Db> Display for AuthControlIn<U, Db> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(
+            f, "AuthControlIn {}: {} policy caught a user {}",
+            std::any::type_name::<Db>(),
+            U::display(),
+            self.auth_result
+        )
+    }
+}
+
+// App-level value of the crate, added either as a `Data<T>`, like the others,
+// or as a bare `T`
+pub(crate) fn app_data<T: Clone + 'static>(req: &HttpRequest) -> Option<T> {
+    req.app_data::<Data<T>>()
+        .map(|data| data.get_ref().clone())
+        .or_else(|| req.app_data::<T>().cloned())
+}
+
+// The pipeline shared by the extractors: parse the credentials, check them
+// against the credential source, and apply the `U` policy
+fn check_request<U: UserControlPolicy>(
+    req: &HttpRequest,
+    credential_source: CredentialSource
+) -> LocalBoxFuture<'static, Result<AuthResult, AuthError>> {
+    let mut config = req.app_data::<BasicAuthConfig>().cloned().unwrap_or_default();
+    if let Some(realm) = U::realm() {
+        config.realm = realm.to_owned();
+    }
+    let login_tracker = req.app_data::<Data<LoginTracker>>().cloned();
+    let auth_stats = req.app_data::<Data<AuthStats>>().cloned();
+    let verification_limiter = req.app_data::<Data<VerificationLimiter>>().cloned();
+    let response_map = app_data::<ResponseMap>(req);
+    let credential_status = CredentialStatus::from_headers(req.headers(), &config);
+    let proxy_user = config.proxy_authenticated_user(req);
+    let insecure_transport = config.require_secure_transport && !config.is_secure_transport(req);
+    // Applies whatever the credentials, and before any of them is checked.
+    // Clients of unknown IP share a bucket.
+    let throttled = match req.app_data::<Data<IpRateLimiter>>() {
+        Some(ip_rate_limiter) => !ip_rate_limiter.try_acquire(config.client_ip(req)),
+        None => false,
+    };
+    let req = req.clone();
+
+    async move {
+        if throttled {
+            return Err(AuthError::new(FailureKind::Throttled, Error::TooManyRequests, None));
+        }
+
+        // Credentials sent in plaintext, even malformed ones, are refused
+        // before going anywhere near the stored hashes
+        let credentials_sent = match credential_status {
+            CredentialStatus::Absent => false,
+            CredentialStatus::Present(_) | CredentialStatus::Malformed(_) => true,
+        };
+        if credentials_sent && insecure_transport {
+            return Err(AuthError::new(
+                FailureKind::InsecureTransport, Error::InsecureTransport, None
+            ));
+        }
+
+        let auth_result = match proxy_user {
+            // The upstream proxy already authenticated the user
+            Some(user) => AuthResult::LoggedUser { user },
+            None => authenticate(
+                &credential_source, credential_status, &config, verification_limiter.as_ref()
+            ).await?,
+        };
+
+        if let (Some(login_tracker), AuthResult::LoggedUser { user }) = (&login_tracker, &auth_result) {
+            login_tracker.record(user);
+        }
+
+        // At this point, authentication is done. It's time for user
+        // access control. The "U" type represents the chosen
+        // UserControlPolicy.
+        // An anonymous user is asked to log in (401), while a logged
+        // user is simply denied (403). Over an insecure transport, the
+        // anonymous user is not asked to send credentials that would be
+        // refused anyway.
+        match auth_result {
+            _ if U::allows_request(&auth_result, &req) => Ok(auth_result),
+            AuthResult::Anonymous if insecure_transport => Err(AuthError::new(
+                FailureKind::InsecureTransport, Error::InsecureTransport, None
+            )),
+            AuthResult::Anonymous => Err(AuthError::new(
+                FailureKind::AuthenticationRequired, Error::MissingCredentials, config.challenge()
+            )),
+            AuthResult::LoggedUser { .. } => Err(AuthError::new(
+                FailureKind::Forbidden, Error::InsufficientPrivileges, None
+            )),
+        }
+    }
+        .map(move |res| {
+            if let Some(auth_stats) = &auth_stats {
+                match &res {
+                    Ok(auth_result) => auth_stats.record_success(auth_result),
+                    Err(auth_error) => auth_stats.record_failure(auth_error.kind()),
                 }
-            },
-            Ok(None) => AuthResult::Anonymous,
-            Err(msg) => {
-                return err(ErrorUnauthorized(format!(
-                    "Malformed authorization header: {}", msg
-                )))
             }
+            res.map_err(|auth_error| auth_error.mapped(response_map.as_ref()))
+        })
+        .boxed_local()
+}
+
+impl<U: UserControlPolicy> AuthControl<U> {
+    // Run the whole pipeline of `from_request` (parsing, verification and
+    // user control policy) on the raw value of an Authorization header, e.g.
+    // in a gRPC interceptor or a CLI. Verification runs on the calling
+    // thread, even for expensive schemes.
+    pub fn from_header_value(
+        header: Option<&str>,
+        htpasswd_database: &HtpasswdDatabase,
+        config: &BasicAuthConfig
+    ) -> Result<AuthControl<U>, Error> {
+        let auth_result = match AuthData::from_header_value(header, config)? {
+            Some(auth_data) => {
+                let password = config.candidate_password(auth_data.password());
+                let stored_hashes = htpasswd_database.get(&auth_data.user);
+                let dummy_hash = if stored_hashes.is_empty() { htpasswd_database.dummy_hash() } else { None };
+                if !password_hash::verify_any(stored_hashes, dummy_hash.as_ref(), password) {
+                    return Err(Error::InvalidCredentials);
+                }
+                // Only told once the password is known to be correct
+                if htpasswd_database.is_expired(&auth_data.user) {
+                    return Err(Error::PasswordExpired { user: auth_data.user });
+                }
+                AuthResult::LoggedUser { user: auth_data.user }
+            }
+            None => AuthResult::Anonymous,
         };
 
-        // At this point, authentication is done. It's time for user access
-        // control. The "U" type represents the chosen UserControlPolicy.
-        if U::allows(&auth_result) {
-            ok(AuthControl {
+        match auth_result {
+            _ if U::allows(&auth_result) => Ok(AuthControl {
                 _phantom_data: PhantomData,
                 auth_result,
-            })
-        } else {
-            err(ErrorForbidden(format!(
-                "Insufficient privileges to access this resource"
-            )))
+            }),
+            AuthResult::Anonymous => Err(Error::MissingCredentials),
+            AuthResult::LoggedUser { .. } => Err(Error::InsufficientPrivileges),
         }
     }
 }
 
+// Where the stored password hashes are taken from
+enum CredentialSource {
+    Database(Data<HtpasswdDatabase>),
+    Typed(Arc<dyn AsRef<HtpasswdDatabase>>),
+    Merged(Data<MergedReloadableDatabase>),
+    Lookup(HashLookup),
+}
+impl CredentialSource {
+    async fn stored_hashes(&self, user: &str) -> Vec<PasswordHash> {
+        match self {
+            CredentialSource::Database(htpasswd_database) => htpasswd_database.get(user).to_vec(),
+            CredentialSource::Typed(htpasswd_database) => (**htpasswd_database).as_ref().get(user).to_vec(),
+            CredentialSource::Merged(merged) => merged.get(user),
+            CredentialSource::Lookup(hash_lookup) => {
+                hash_lookup.lookup(user).await.into_iter().collect()
+            }
+        }
+    }
+
+    fn dummy_hash(&self) -> Option<PasswordHash> {
+        match self {
+            CredentialSource::Database(htpasswd_database) => htpasswd_database.dummy_hash(),
+            CredentialSource::Typed(htpasswd_database) => (**htpasswd_database).as_ref().dummy_hash(),
+            CredentialSource::Merged(merged) => merged.dummy_hash(),
+            CredentialSource::Lookup(hash_lookup) => hash_lookup.dummy_hash(),
+        }
+    }
+
+    // Lookups only provide hashes, so their passwords never expire
+    fn is_expired(&self, user: &str) -> bool {
+        match self {
+            CredentialSource::Database(htpasswd_database) => htpasswd_database.is_expired(user),
+            CredentialSource::Typed(htpasswd_database) => (**htpasswd_database).as_ref().is_expired(user),
+            CredentialSource::Merged(merged) => merged.is_expired(user),
+            CredentialSource::Lookup(_) => false,
+        }
+    }
+}
+
+// Match the authentication data extracted from the request against the
+// stored password hashes
+async fn authenticate(
+    credential_source: &CredentialSource,
+    credential_status: CredentialStatus,
+    config: &BasicAuthConfig,
+    verification_limiter: Option<&Data<VerificationLimiter>>,
+) -> Result<AuthResult, AuthError> {
+    match credential_status {
+        CredentialStatus::Present(auth_data) => {
+            let stored_hashes = credential_source.stored_hashes(&auth_data.user).await;
+            let stored_schemes: Vec<String> = stored_hashes.iter()
+                .map(|password_hash| password_hash.scheme().to_string())
+                .collect();
+
+            let user_is_known = !stored_hashes.is_empty();
+            let dummy_hash = if user_is_known { None } else { credential_source.dummy_hash() };
+
+            if is_valid(stored_hashes, dummy_hash, &auth_data, config, verification_limiter).await? {
+                // Only told once the password is known to be correct
+                if credential_source.is_expired(&auth_data.user) {
+                    log::info!("Failed login for user {:?}: password expired", auth_data.user);
+                    let user = auth_data.user;
+                    let attempted_user = if config.expose_attempted_user {
+                        Some(user.clone())
+                    } else {
+                        None
+                    };
+                    return Err(AuthError::new(FailureKind::PasswordExpired, Error::PasswordExpired { user }, None)
+                        .with_attempted_user(attempted_user));
+                }
+                Ok(AuthResult::LoggedUser {
+                    user: auth_data.user,
+                })
+            } else {
+                // Server-side only: telling the client whether the user
+                // exists (or how its password is stored) would allow user
+                // enumeration.
+                if stored_schemes.is_empty() {
+                    log::info!(
+                        "Failed login for unknown user {:?}",
+                        auth_data.user
+                    );
+                } else {
+                    log::info!(
+                        "Failed login for user {:?}: wrong password (stored schemes: {})",
+                        auth_data.user, stored_schemes.join(", ")
+                    );
+                }
+                let kind = if user_is_known {
+                    FailureKind::WrongPassword
+                } else {
+                    FailureKind::UnknownUser
+                };
+                let attempted_user = if config.expose_attempted_user {
+                    Some(auth_data.user)
+                } else {
+                    None
+                };
+                Err(AuthError::new(kind, Error::InvalidCredentials, config.challenge())
+                    .with_attempted_user(attempted_user))
+            }
+        },
+        CredentialStatus::Absent => Ok(AuthResult::Anonymous),
+        CredentialStatus::Malformed(error) => {
+            let kind = match error {
+                Error::EmptyPassword => FailureKind::EmptyPassword,
+                _ => FailureKind::MalformedHeader,
+            };
+            Err(AuthError::new(kind, error, config.challenge()))
+        },
+    }
+}
+
+// Check the supplied password against the stored hashes, or the dummy hash
+// for an unknown user. Expensive schemes (scrypt) are verified on the
+// blocking thread pool, so that they don't stall the async runtime, within
+// the limit of the VerificationLimiter if any. A dummy hash goes through the
+// same path as a real one, so that unknown users cost the same.
+async fn is_valid(
+    stored_hashes: Vec<PasswordHash>,
+    dummy_hash: Option<PasswordHash>,
+    auth_data: &AuthData,
+    config: &BasicAuthConfig,
+    verification_limiter: Option<&Data<VerificationLimiter>>,
+) -> Result<bool, AuthError> {
+    if !stored_hashes.iter().chain(&dummy_hash).any(PasswordHash::is_expensive) {
+        let password = config.candidate_password(auth_data.password());
+        return Ok(password_hash::verify_any(&stored_hashes, dummy_hash.as_ref(), password));
+    }
+
+    let permit = match verification_limiter {
+        Some(verification_limiter) => Some(
+            VerificationLimiter::try_acquire(verification_limiter)
+                .ok_or_else(|| AuthError::new(FailureKind::Overloaded, Error::TooManyVerifications, None))?
+        ),
+        None => None,
+    };
+
+    // Moved as-is, so that a secret password stays wrapped until used
+    let (auth_data, config) = (auth_data.clone(), config.clone());
+    // The blocking call can only fail if the thread pool is gone, in which
+    // case the credentials are simply not accepted
+    Ok(web::block(move || {
+        let _permit = permit;
+        let password = config.candidate_password(auth_data.password());
+        Ok::<_, ()>(password_hash::verify_any(&stored_hashes, dummy_hash.as_ref(), password))
+    })
+        .await
+        .unwrap_or(false))
+}
+
+// The decision shown is the one of `U::allows`, without the request: a
+// request-aware policy (see `UserControlPolicy::allows_request`) may have
+// decided otherwise
 impl<U: UserControlPolicy> Display for AuthControl<U> {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
         write!(
@@ -110,3 +476,496 @@ impl<U: UserControlPolicy> Display for AuthControl<U> {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        test,
+        App,
+        ResponseError,
+        http::{
+            StatusCode,
+            header::{
+                AUTHORIZATION,
+                HeaderName,
+                WWW_AUTHENTICATE,
+            },
+        },
+    };
+
+    use super::*;
+    use crate::{
+        auth_data::basic_auth_header,
+        user_control_policy::{
+            AnyLoggedUser,
+            Anyone,
+        },
+    };
+    #[cfg(feature = "scrypt")]
+    use crate::password_hash::tests::cheap_scrypt_hash;
+
+    async fn logged_user(auth_control: AuthControl<AnyLoggedUser>) -> HttpResponse {
+        HttpResponse::Ok().body(auth_control.auth_result.to_string())
+    }
+
+    #[actix_rt::test]
+    async fn cheap_verifications_are_not_limited() {
+        let mut htpasswd_database = HtpasswdDatabase::new();
+        htpasswd_database.add("alice", "secret").unwrap();
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(Data::new(htpasswd_database))
+                .app_data(Data::new(VerificationLimiter::new(0)))
+                .route("/", web::get().to(logged_user))
+        ).await;
+        let req = test::TestRequest::get()
+            .header(AUTHORIZATION, basic_auth_header("alice", "secret"))
+            .to_request();
+        assert_eq!(test::call_service(&mut app, req).await.status(), StatusCode::OK);
+    }
+
+    #[cfg(feature = "scrypt")]
+    #[actix_rt::test]
+    async fn expensive_verifications_over_the_limit_get_a_503() {
+        let mut htpasswd_database = HtpasswdDatabase::new();
+        htpasswd_database.upsert("alice", cheap_scrypt_hash("secret"));
+        let htpasswd_database = Data::new(htpasswd_database);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(htpasswd_database.clone())
+                .app_data(Data::new(VerificationLimiter::new(0)))
+                .route("/", web::get().to(logged_user))
+        ).await;
+        let req = test::TestRequest::get()
+            .header(AUTHORIZATION, basic_auth_header("alice", "secret"))
+            .to_request();
+        assert_eq!(test::call_service(&mut app, req).await.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(htpasswd_database)
+                .app_data(Data::new(VerificationLimiter::new(1)))
+                .route("/", web::get().to(logged_user))
+        ).await;
+        let req = test::TestRequest::get()
+            .header(AUTHORIZATION, basic_auth_header("alice", "secret"))
+            .to_request();
+        assert_eq!(test::call_service(&mut app, req).await.status(), StatusCode::OK);
+    }
+
+    // The dummy hash of an unknown user goes through the same limited path
+    // as the scrypt hash of a known user
+    #[cfg(feature = "scrypt")]
+    #[actix_rt::test]
+    async fn unknown_users_are_verified_like_scrypt_users() {
+        let mut htpasswd_database = HtpasswdDatabase::new();
+        htpasswd_database.upsert("alice", cheap_scrypt_hash("secret"));
+        let htpasswd_database = Data::new(htpasswd_database);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(htpasswd_database.clone())
+                .app_data(Data::new(VerificationLimiter::new(0)))
+                .route("/", web::get().to(logged_user))
+        ).await;
+        let req = test::TestRequest::get()
+            .header(AUTHORIZATION, basic_auth_header("mallory", "secret"))
+            .to_request();
+        assert_eq!(test::call_service(&mut app, req).await.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(htpasswd_database)
+                .route("/", web::get().to(logged_user))
+        ).await;
+        let req = test::TestRequest::get()
+            .header(AUTHORIZATION, basic_auth_header("mallory", "secret"))
+            .to_request();
+        assert_eq!(test::call_service(&mut app, req).await.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_rt::test]
+    async fn disabled_basic_gets_a_403_without_challenge() {
+        let mut htpasswd_database = HtpasswdDatabase::new();
+        htpasswd_database.add("alice", "secret").unwrap();
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(Data::new(htpasswd_database))
+                .app_data(BasicAuthConfig::new().accept_basic(false))
+                .route("/", web::get().to(logged_user))
+        ).await;
+        for req in [
+            test::TestRequest::get().header(AUTHORIZATION, basic_auth_header("alice", "secret")),
+            test::TestRequest::get(),
+        ] {
+            let res = test::call_service(&mut app, req.to_request()).await;
+            assert_eq!(res.status(), StatusCode::FORBIDDEN);
+            assert!(res.headers().get(WWW_AUTHENTICATE).is_none());
+        }
+    }
+
+    async fn authenticate_header(
+        htpasswd_database: HtpasswdDatabase,
+        header: &str,
+        config: &BasicAuthConfig
+    ) -> Result<AuthResult, AuthError> {
+        let credential_status = match AuthData::from_header_value(Some(header), config) {
+            Ok(Some(auth_data)) => CredentialStatus::Present(auth_data),
+            Ok(None) => CredentialStatus::Absent,
+            Err(error) => CredentialStatus::Malformed(error),
+        };
+        let credential_source = CredentialSource::Database(Data::new(htpasswd_database));
+        authenticate(&credential_source, credential_status, config, None).await
+    }
+
+    #[actix_rt::test]
+    async fn attempted_user_is_only_kept_when_exposed() {
+        let mut htpasswd_database = HtpasswdDatabase::new();
+        htpasswd_database.add("alice", "secret").unwrap();
+        let header = basic_auth_header("alice", "wrong");
+
+        let config = BasicAuthConfig::new().expose_attempted_user(true);
+        let auth_error = authenticate_header(htpasswd_database.clone(), &header, &config).await.unwrap_err();
+        assert_eq!(auth_error.kind(), FailureKind::WrongPassword);
+        assert_eq!(auth_error.attempted_user(), Some("alice"));
+
+        let config = BasicAuthConfig::new();
+        let auth_error = authenticate_header(htpasswd_database, &header, &config).await.unwrap_err();
+        assert_eq!(auth_error.attempted_user(), None);
+    }
+
+    async fn anyone(auth_control: AuthControl<Anyone>) -> HttpResponse {
+        HttpResponse::Ok().body(auth_control.auth_result.to_string())
+    }
+
+    #[actix_rt::test]
+    async fn insecure_transport_is_never_challenged() {
+        let mut htpasswd_database = HtpasswdDatabase::new();
+        htpasswd_database.add("alice", "secret").unwrap();
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(Data::new(htpasswd_database))
+                .app_data(BasicAuthConfig::new().require_secure_transport(true))
+                .route("/", web::get().to(logged_user))
+                .route("/public", web::get().to(anyone))
+        ).await;
+        for req in [
+            test::TestRequest::get().header(AUTHORIZATION, basic_auth_header("alice", "secret")),
+            test::TestRequest::get().header(AUTHORIZATION, "Basic !!!"),
+            test::TestRequest::get(),
+        ] {
+            let res = test::call_service(&mut app, req.to_request()).await;
+            assert_eq!(res.status(), StatusCode::FORBIDDEN);
+            assert!(res.headers().get(WWW_AUTHENTICATE).is_none());
+        }
+
+        // Anonymous requests allowed by the policy still go through
+        let req = test::TestRequest::get().uri("/public").to_request();
+        assert_eq!(test::call_service(&mut app, req).await.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn password_expiry_is_only_told_after_a_valid_password() {
+        let mut htpasswd_database = HtpasswdDatabase::new();
+        htpasswd_database.add_with_expiry("alice", "secret", std::time::SystemTime::now()).unwrap();
+        let config = BasicAuthConfig::new();
+
+        let header = basic_auth_header("alice", "secret");
+        let auth_error = authenticate_header(htpasswd_database.clone(), &header, &config).await.unwrap_err();
+        assert_eq!(auth_error.kind(), FailureKind::PasswordExpired);
+        assert_eq!(auth_error.status_code(), StatusCode::FORBIDDEN);
+
+        let header = basic_auth_header("alice", "wrong");
+        let auth_error = authenticate_header(htpasswd_database, &header, &config).await.unwrap_err();
+        assert_eq!(auth_error.kind(), FailureKind::WrongPassword);
+    }
+
+    #[test]
+    fn from_header_value_runs_the_whole_pipeline() {
+        let mut htpasswd_database = HtpasswdDatabase::new();
+        htpasswd_database.add("alice", "secret").unwrap();
+        let config = BasicAuthConfig::new();
+        // "alice:secret" and "alice:wrong", encoded by hand
+        let valid = Some("Basic YWxpY2U6c2VjcmV0");
+        let wrong = Some("Basic YWxpY2U6d3Jvbmc=");
+
+        let auth_control = AuthControl::<AnyLoggedUser>::from_header_value(valid, &htpasswd_database, &config)
+            .unwrap();
+        assert_eq!(auth_control.auth_result, AuthResult::LoggedUser { user: "alice".to_owned() });
+
+        match AuthControl::<AnyLoggedUser>::from_header_value(wrong, &htpasswd_database, &config) {
+            Err(Error::InvalidCredentials) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+        match AuthControl::<AnyLoggedUser>::from_header_value(None, &htpasswd_database, &config) {
+            Err(Error::MissingCredentials) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+        match AuthControl::<AnyLoggedUser>::from_header_value(Some("Bearer abcdef"), &htpasswd_database, &config) {
+            Err(Error::UnsupportedScheme { scheme }) => assert_eq!(scheme, "Bearer"),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let auth_control = AuthControl::<Anyone>::from_header_value(None, &htpasswd_database, &config)
+            .unwrap();
+        assert_eq!(auth_control.auth_result, AuthResult::Anonymous);
+    }
+
+    // Denies everybody, logged or not
+    struct Nobody;
+    impl UserControlPolicy for Nobody {
+        fn display() -> &'static str {
+            "Nobody"
+        }
+        fn allows(_auth_result: &AuthResult) -> bool {
+            false
+        }
+    }
+
+    async fn nobody(_: AuthControl<Nobody>) -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    #[actix_rt::test]
+    async fn anonymous_users_are_challenged_while_logged_users_are_forbidden() {
+        let mut htpasswd_database = HtpasswdDatabase::new();
+        htpasswd_database.add("alice", "secret").unwrap();
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(Data::new(htpasswd_database))
+                .route("/", web::get().to(nobody))
+        ).await;
+
+        let res = test::call_service(&mut app, test::TestRequest::get().to_request()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(res.headers().get(WWW_AUTHENTICATE).unwrap(), "Basic realm=\"Restricted\"");
+
+        let req = test::TestRequest::get()
+            .header(AUTHORIZATION, basic_auth_header("alice", "secret"))
+            .to_request();
+        let res = test::call_service(&mut app, req).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        assert!(res.headers().get(WWW_AUTHENTICATE).is_none());
+    }
+
+    #[actix_rt::test]
+    async fn proxy_user_header_is_only_honored_from_trusted_proxies() {
+        let config = BasicAuthConfig::new()
+            .trusted_proxies(vec!["10.0.0.1".parse().unwrap()])
+            .proxy_user_header(HeaderName::from_static("x-authenticated-user"));
+        let mut app = test::init_service(
+            App::new()
+                .app_data(Data::new(HtpasswdDatabase::new()))
+                .app_data(config)
+                .route("/", web::get().to(logged_user))
+                .route("/nobody", web::get().to(nobody))
+        ).await;
+        let req = |uri: &str, peer_addr: &str| test::TestRequest::get()
+            .uri(uri)
+            .peer_addr(peer_addr.parse().unwrap())
+            .header("X-Authenticated-User", "alice")
+            .to_request();
+
+        let res = test::call_service(&mut app, req("/", "10.0.0.1:4242")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(test::read_body(res).await, "authenticated as \"alice\"");
+
+        // Anybody else is anonymous, whatever the header says
+        let res = test::call_service(&mut app, req("/", "203.0.113.7:4242")).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        // The user policy still applies to the forwarded user
+        let res = test::call_service(&mut app, req("/nobody", "10.0.0.1:4242")).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+
+    struct Admins;
+    impl UserControlPolicy for Admins {
+        fn display() -> &'static str {
+            "Admins"
+        }
+        fn allows(auth_result: &AuthResult) -> bool {
+            matches!(auth_result, AuthResult::LoggedUser { user } if user == "admin")
+        }
+        fn realm() -> Option<&'static str> {
+            Some("Administration")
+        }
+    }
+
+    async fn admins(_: AuthControl<Admins>) -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    #[actix_rt::test]
+    async fn policy_realm_takes_precedence_in_the_challenge() {
+        let mut htpasswd_database = HtpasswdDatabase::new();
+        htpasswd_database.add("alice", "secret").unwrap();
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(Data::new(htpasswd_database))
+                .app_data(BasicAuthConfig::new().realm("Intranet"))
+                .route("/", web::get().to(logged_user))
+                .route("/admin", web::get().to(admins))
+        ).await;
+
+        let req = test::TestRequest::get().uri("/admin").to_request();
+        let res = test::call_service(&mut app, req).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(res.headers().get(WWW_AUTHENTICATE).unwrap(), "Basic realm=\"Administration\"");
+
+        // Also on a wrong password
+        let req = test::TestRequest::get()
+            .uri("/admin")
+            .header(AUTHORIZATION, basic_auth_header("alice", "wrong"))
+            .to_request();
+        let res = test::call_service(&mut app, req).await;
+        assert_eq!(res.headers().get(WWW_AUTHENTICATE).unwrap(), "Basic realm=\"Administration\"");
+
+        // The other policies keep the realm of the config
+        let res = test::call_service(&mut app, test::TestRequest::get().to_request()).await;
+        assert_eq!(res.headers().get(WWW_AUTHENTICATE).unwrap(), "Basic realm=\"Intranet\"");
+    }
+
+    async fn json_body(auth_result: AuthResult) -> String {
+        let req = test::TestRequest::default().to_http_request();
+        let res = auth_result.respond_to(&req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        match res.body().as_ref() {
+            Some(actix_web::body::Body::Bytes(bytes)) => String::from_utf8(bytes.to_vec()).unwrap(),
+            other => panic!("unexpected body {:?}", other),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn auth_result_responds_with_its_identity() {
+        assert_eq!(
+            json_body(AuthResult::LoggedUser { user: "alice".to_owned() }).await,
+            r#"{"authenticated":true,"user":"alice"}"#
+        );
+        assert_eq!(json_body(AuthResult::Anonymous).await, r#"{"authenticated":false}"#);
+    }
+
+    #[actix_rt::test]
+    async fn throttled_requests_get_a_429_before_any_credential_check() {
+        let lookups = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let hash_lookup = {
+            let lookups = lookups.clone();
+            HashLookup::new(move |_user| {
+                lookups.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                futures::future::ready(Some(PasswordHash::sha1("secret")))
+            })
+        };
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(Data::new(hash_lookup))
+                .app_data(BasicAuthConfig::new().trusted_proxies(vec!["10.0.0.1".parse().unwrap()]))
+                .app_data(Data::new(IpRateLimiter::new(1, 0.0)))
+                .route("/", web::get().to(logged_user))
+        ).await;
+        // The client makes up a new leftmost hop each time, to no avail
+        let req = |spoofed_hop: &str, password: &str| test::TestRequest::get()
+            .peer_addr("10.0.0.1:4242".parse().unwrap())
+            .header("X-Forwarded-For", format!("{}, 198.51.100.1", spoofed_hop))
+            .header(AUTHORIZATION, basic_auth_header("alice", password))
+            .to_request();
+
+        let res = test::call_service(&mut app, req("1.1.1.1", "wrong")).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(lookups.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        for (spoofed_hop, password) in [("2.2.2.2", "wrong"), ("3.3.3.3", "secret")] {
+            let res = test::call_service(&mut app, req(spoofed_hop, password)).await;
+            assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        }
+        assert_eq!(lookups.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[actix_rt::test]
+    async fn made_up_forwarded_headers_do_not_dodge_the_limit() {
+        let mut htpasswd_database = HtpasswdDatabase::new();
+        htpasswd_database.add("alice", "secret").unwrap();
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(Data::new(htpasswd_database))
+                .app_data(BasicAuthConfig::new().trusted_proxies(vec!["10.0.0.1".parse().unwrap()]))
+                .app_data(Data::new(IpRateLimiter::new(1, 0.0)))
+                .route("/", web::get().to(logged_user))
+        ).await;
+        let req = |forwarded: &str| test::TestRequest::get()
+            .peer_addr("10.0.0.1:4242".parse().unwrap())
+            .header("Forwarded", forwarded)
+            .header(AUTHORIZATION, basic_auth_header("alice", "wrong"))
+            .to_request();
+
+        // Without any X-Forwarded-For, the client IP is unknown
+        let res = test::call_service(&mut app, req("for=1.1.1.1")).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        for forwarded in ["for=2.2.2.2", "for=unknown", "for=3.3.3.3"] {
+            let res = test::call_service(&mut app, req(forwarded)).await;
+            assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS, "{}", forwarded);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn response_map_is_used_as_data_or_bare() {
+        let response_map = ResponseMap::new()
+            .status(FailureKind::MalformedHeader, StatusCode::BAD_REQUEST);
+        let as_data = App::new().app_data(Data::new(response_map.clone()));
+        let bare = App::new().app_data(response_map);
+
+        for app in [as_data, bare] {
+            let mut app = test::init_service(
+                app
+                    .app_data(Data::new(HtpasswdDatabase::new()))
+                    .route("/", web::get().to(logged_user))
+            ).await;
+            let req = test::TestRequest::get().header(AUTHORIZATION, "Basic !!!").to_request();
+            assert_eq!(test::call_service(&mut app, req).await.status(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    struct AdminDb(HtpasswdDatabase);
+    impl AsRef<HtpasswdDatabase> for AdminDb {
+        fn as_ref(&self) -> &HtpasswdDatabase {
+            &self.0
+        }
+    }
+
+    #[test]
+    fn auth_control_is_displayed_with_the_decision_of_the_policy() {
+        let auth_control = AuthControl::<AnyLoggedUser> {
+            _phantom_data: PhantomData,
+            auth_result: AuthResult::LoggedUser { user: "alice".to_owned() },
+        };
+        assert_eq!(
+            auth_control.to_string(),
+            "AuthControl: AnyLoggedUser policy caught a user authenticated as \"alice\": access granted"
+        );
+
+        let auth_control = AuthControl::<AnyLoggedUser> {
+            _phantom_data: PhantomData,
+            auth_result: AuthResult::Anonymous,
+        };
+        assert!(auth_control.to_string().ends_with(": access forbidden"), "{}", auth_control);
+    }
+
+    #[test]
+    fn auth_control_in_is_displayed_with_its_database() {
+        let auth_control_in = AuthControlIn::<AnyLoggedUser, AdminDb> {
+            _phantom_data: PhantomData,
+            auth_result: AuthResult::LoggedUser { user: "alice".to_owned() },
+        };
+        let displayed = auth_control_in.to_string();
+        assert!(displayed.starts_with("AuthControlIn "), "{}", displayed);
+        assert!(displayed.contains("AdminDb"), "{}", displayed);
+        assert!(displayed.ends_with("AnyLoggedUser policy caught a user authenticated as \"alice\""), "{}", displayed);
+    }
+}
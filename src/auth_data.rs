@@ -1,71 +1,410 @@
+use std::fmt::{
+    Debug,
+    Formatter,
+};
+#[cfg(not(feature = "secrecy"))]
+use std::ops::{
+    Deref,
+    DerefMut,
+};
+
 use actix_web::{
+    FromRequest,
     HttpRequest,
-    http::header::AUTHORIZATION,
+    dev::Payload,
+    error::Error as HttpError,
+    http::{
+        HeaderMap,
+        header::AUTHORIZATION,
+    },
+};
+use futures::future::{
+    ok,
+    Ready
 };
-use serde::{
-    Serialize,
-    Deserialize
+#[cfg(feature = "secrecy")]
+use secrecy::{
+    ExposeSecret,
+    SecretString,
+    zeroize::Zeroizing,
 };
+use serde::Deserialize;
+
+use crate::{
+    basic_auth_config::BasicAuthConfig,
+    error::Error,
+};
+
+// Password submitted by the client, only exposed to verify it against the
+// stored hashes. Its Debug output is redacted. With the "secrecy" feature, it
+// is also held in a `SecretString`, which is zeroized on drop.
+#[derive(Clone, Deserialize)]
+#[serde(from = "String")]
+pub struct SubmittedPassword {
+    #[cfg(feature = "secrecy")]
+    password: SecretString,
+    #[cfg(not(feature = "secrecy"))]
+    password: String,
+}
+impl SubmittedPassword {
+    #[cfg(feature = "secrecy")]
+    pub fn expose_password(&self) -> &str {
+        self.password.expose_secret()
+    }
+
+    #[cfg(not(feature = "secrecy"))]
+    pub fn expose_password(&self) -> &str {
+        &self.password
+    }
+}
+
+impl From<String> for SubmittedPassword {
+    #[cfg(feature = "secrecy")]
+    fn from(password: String) -> SubmittedPassword {
+        SubmittedPassword {
+            password: SecretString::new(password),
+        }
+    }
+
+    #[cfg(not(feature = "secrecy"))]
+    fn from(password: String) -> SubmittedPassword {
+        SubmittedPassword {
+            password,
+        }
+    }
+}
+
+impl Debug for SubmittedPassword {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "SubmittedPassword([REDACTED])")
+    }
+}
+
+// Without the "secrecy" feature, the temporary copies of the credentials are
+// not zeroized either
+#[cfg(not(feature = "secrecy"))]
+struct Zeroizing<T>(T);
+#[cfg(not(feature = "secrecy"))]
+impl<T> Zeroizing<T> {
+    fn new(value: T) -> Zeroizing<T> {
+        Zeroizing(value)
+    }
+}
+#[cfg(not(feature = "secrecy"))]
+impl<T> Deref for Zeroizing<T> {
+    type Target = T;
 
-use crate::error::Error;
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+#[cfg(not(feature = "secrecy"))]
+impl<T> DerefMut for Zeroizing<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
 
-#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
-pub(crate) struct AuthData {
+// The password is neither compared nor serialized
+#[derive(Clone, Debug, Deserialize)]
+pub struct AuthData {
     pub(crate) user: String,
-    pub(crate) password: String,
+    pub(crate) password: SubmittedPassword,
 }
 impl AuthData {
-    pub(crate) fn from_request(req: &HttpRequest) -> Result<Option<AuthData>, Error> {
+    pub fn user(&self) -> &str {
+        &self.user
+    }
+
+    pub(crate) fn password(&self) -> &str {
+        self.password.expose_password()
+    }
+
+    pub(crate) fn from_headers(
+        headers: &HeaderMap,
+        config: &BasicAuthConfig
+    ) -> Result<Option<AuthData>, Error> {
         // Credits to https://github.com/actix/actix-web-httpauth/blob/master/src/headers/authorization/scheme/basic.rs
 
-        let header = match req.headers().get(AUTHORIZATION) {
+        let header = match headers.get(AUTHORIZATION) {
+            Some(header) => header.to_str()
+                .or(Err(Error::CannotConvertHeaderToString))?,
+            None => return Ok(None),
+        };
+        AuthData::from_header_value(Some(header), config)
+    }
+
+    // Same as `from_headers`, but with the raw value of the Authorization
+    // header, for callers outside of actix
+    pub(crate) fn from_header_value(
+        header: Option<&str>,
+        config: &BasicAuthConfig
+    ) -> Result<Option<AuthData>, Error> {
+        let header = match header {
             Some(header) => header,
             None => return Ok(None),
         };
 
+        // Normalize the header in one pass: surrounding whitespace is
+        // ignored, and the scheme is split from the credentials on the first
+        // run of whitespace (some clients send several spaces, or a tab)
+        let header = header.trim();
+
         // "Basic *" length
         if header.len() < 7 {
             return Err(Error::HeaderNotLongEnough);
         }
 
-        let mut parts = header.to_str()
-            .or(Err(Error::CannotConvertHeaderToString))?
-            .splitn(2, ' ');
+        let (scheme, encoded_user_password) = match header.find(char::is_whitespace) {
+            Some(i) => (&header[..i], header[i..].trim_start()),
+            None => (header, ""),
+        };
 
-        // Check the authentication scheme. Only Basic authentication is
-        // supported.
-        match parts.next() {
-            Some(scheme) => {
-                if scheme != "Basic" {
-                    return Err(Error::UnsupportedScheme {
-                        scheme: scheme.to_owned(),
-                    });
-                }
-            }
-            None => return Err(Error::MissingScheme),
+        // Check the authentication scheme, which is case-insensitive. Only
+        // Basic authentication is supported, and it can even be disabled.
+        if !scheme.eq_ignore_ascii_case("Basic") || !config.accept_basic {
+            return Err(Error::UnsupportedScheme {
+                scheme: scheme.to_owned(),
+            });
         }
 
-        let raw_user_password = base64::decode(parts.next()
-            .ok_or(Error::MalformedCredentials)?)
+        if encoded_user_password.is_empty() {
+            return Err(Error::MalformedCredentials);
+        }
+        // Refuse oversized credentials before allocating anything for them.
+        // Every 4 base64 characters decode to 3 bytes, and the trailing
+        // padding to nothing. The raw length is bounded first, as the decoder
+        // allocates for the whole input, padding included.
+        let max_encoded_length = config.max_credentials_length.div_ceil(3).saturating_mul(4);
+        if encoded_user_password.len() > max_encoded_length {
+            return Err(Error::CredentialsTooLong {
+                max_length: config.max_credentials_length,
+            });
+        }
+        let unpadded_length = encoded_user_password.trim_end_matches('=').len();
+        let decoded_length = unpadded_length / 4 * 3 + unpadded_length % 4 * 3 / 4;
+        if decoded_length > config.max_credentials_length {
+            return Err(Error::CredentialsTooLong {
+                max_length: config.max_credentials_length,
+            });
+        }
+        // The decoded credentials hold the password: every temporary copy of
+        // it is zeroized on drop. The buffer is allocated upfront so that it
+        // is never moved around while growing.
+        let mut raw_user_password = Zeroizing::new(Vec::with_capacity(decoded_length));
+        base64::decode_config_buf(encoded_user_password, base64::STANDARD, &mut raw_user_password)
             .or(Err(Error::MalformedCredentials))?;
 
-        let owned_user_password = String::from_utf8_lossy(&raw_user_password);
+        let owned_user_password = match String::from_utf8(std::mem::take(&mut *raw_user_password)) {
+            Ok(user_password) => Zeroizing::new(user_password),
+            Err(error) => {
+                let raw_user_password = Zeroizing::new(error.into_bytes());
+                Zeroizing::new(String::from_utf8_lossy(&raw_user_password).into_owned())
+            }
+        };
         let mut user_password = owned_user_password.splitn(2, ':');
 
         let user = user_password.next()
-            .ok_or(Error::CannotExtractUsername)?
-            .to_string();
+            .ok_or(Error::CannotExtractUsername)?;
         let password = user_password.next()
-            .ok_or(Error::CannotExtractPassword)?
-            .to_string();
+            .ok_or(Error::CannotExtractPassword)?;
 
+        if user.is_empty() && !config.allow_empty_username {
+            return Err(Error::EmptyUsername);
+        }
         if password.is_empty() {
             return Err(Error::EmptyPassword);
         }
 
+        // The only copy of the password which outlives this function
         Ok(Some(AuthData {
-            user,
-            password
+            user: user.to_owned(),
+            password: SubmittedPassword::from(password.to_owned()),
         }))
     }
 }
+
+// Build the value of an Authorization header carrying the given credentials
+// with the Basic scheme, e.g. to craft requests in tests
+pub fn basic_auth_header(user: &str, password: &str) -> String {
+    format!("Basic {}", base64::encode(&format!("{}:{}", user, password)))
+}
+
+// Outcome of the Authorization header parsing, before any check against the
+// database. `AuthControl` turns `Absent` into an anonymous user and rejects
+// `Malformed` with a 401, but other code can choose differently, e.g. treat
+// malformed credentials as anonymous on public routes.
+#[derive(Debug)]
+pub enum CredentialStatus {
+    Absent,
+    Malformed(Error),
+    Present(AuthData),
+}
+impl CredentialStatus {
+    // Parse the credentials according to the BasicAuthConfig of the app, or
+    // the default one if none was added
+    pub fn from_request(req: &HttpRequest) -> CredentialStatus {
+        match req.app_data::<BasicAuthConfig>() {
+            Some(config) => CredentialStatus::from_headers(req.headers(), config),
+            None => CredentialStatus::from_headers(req.headers(), &BasicAuthConfig::default()),
+        }
+    }
+
+    pub fn from_headers(headers: &HeaderMap, config: &BasicAuthConfig) -> CredentialStatus {
+        match AuthData::from_headers(headers, config) {
+            Ok(Some(auth_data)) => CredentialStatus::Present(auth_data),
+            Ok(None) => CredentialStatus::Absent,
+            Err(error) => CredentialStatus::Malformed(error),
+        }
+    }
+}
+
+impl FromRequest for CredentialStatus {
+    type Error = HttpError;
+    type Future = Ready<Result<Self, HttpError>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ok(CredentialStatus::from_request(req))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::basic_credentials::BasicCredentials;
+
+    fn parse(header: &str, config: &BasicAuthConfig) -> Result<Option<AuthData>, Error> {
+        AuthData::from_header_value(Some(header), config)
+    }
+
+    #[test]
+    fn basic_auth_header_round_trips_through_auth_data() {
+        let config = BasicAuthConfig::new();
+        let auth_data = parse(&basic_auth_header("alice", "secret"), &config).unwrap().unwrap();
+        assert_eq!(auth_data.user(), "alice");
+        assert_eq!(auth_data.password(), "secret");
+
+        // Only the first ':' splits the username from the password
+        let auth_data = parse(&basic_auth_header("bob", "pass:word"), &config).unwrap().unwrap();
+        assert_eq!(auth_data.user(), "bob");
+        assert_eq!(auth_data.password(), "pass:word");
+    }
+
+    #[test]
+    fn debug_output_does_not_contain_the_password() {
+        let header = basic_auth_header("alice", "hunter2");
+        let auth_data = parse(&header, &BasicAuthConfig::new()).unwrap().unwrap();
+        let credentials = BasicCredentials::from(auth_data.clone());
+        assert_eq!(credentials.expose_password(), "hunter2");
+
+        for debug_output in &[
+            format!("{:?}", auth_data),
+            format!("{:?}", credentials),
+            format!("{:?}", CredentialStatus::Present(auth_data)),
+        ] {
+            assert!(debug_output.contains("alice"), "{}", debug_output);
+            assert!(!debug_output.contains("hunter2"), "{}", debug_output);
+        }
+    }
+
+    #[test]
+    fn invalid_utf8_is_replaced() {
+        let header = format!("Basic {}", base64::encode(b"al\xffce:secret"));
+        let auth_data = parse(&header, &BasicAuthConfig::new()).unwrap().unwrap();
+        assert_eq!(auth_data.user(), "al\u{fffd}ce");
+        assert_eq!(auth_data.password(), "secret");
+    }
+
+    #[test]
+    fn basic_is_rejected_when_disabled() {
+        let config = BasicAuthConfig::new().accept_basic(false);
+        match parse(&basic_auth_header("alice", "secret"), &config) {
+            Err(Error::UnsupportedScheme { scheme }) => assert_eq!(scheme, "Basic"),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn empty_username_is_only_accepted_when_allowed() {
+        let header = basic_auth_header("", "secret");
+
+        match parse(&header, &BasicAuthConfig::new()) {
+            Err(Error::EmptyUsername) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let config = BasicAuthConfig::new().allow_empty_username(true);
+        let auth_data = parse(&header, &config).unwrap().unwrap();
+        assert_eq!(auth_data.user(), "");
+        assert_eq!(auth_data.password(), "secret");
+    }
+
+    #[test]
+    fn credentials_up_to_the_limit_are_accepted() {
+        // 10 bytes, encoded with padding
+        let header = basic_auth_header("c", "passwor1");
+        assert!(header.ends_with('='));
+
+        let config = BasicAuthConfig::new().max_credentials_length(10);
+        assert_eq!(parse(&header, &config).unwrap().unwrap().password(), "passwor1");
+        // Same without the padding
+        let unpadded = header.trim_end_matches('=');
+        assert_eq!(parse(unpadded, &config).unwrap().unwrap().password(), "passwor1");
+    }
+
+    #[test]
+    fn credentials_over_the_limit_are_rejected() {
+        let config = BasicAuthConfig::new().max_credentials_length(10);
+        for header in &[basic_auth_header("c", "passwor12"), basic_auth_header("alice", "secret")] {
+            match parse(header, &config) {
+                Err(Error::CredentialsTooLong { max_length }) => assert_eq!(max_length, 10),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+
+        // Padding decodes to nothing, but still has to be allocated for
+        let padded = format!("{}{}", basic_auth_header("c", "passwor1"), "=".repeat(10_000));
+        match parse(&padded, &config) {
+            Err(Error::CredentialsTooLong { max_length }) => assert_eq!(max_length, 10),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let oversized = basic_auth_header("alice", &"x".repeat(5000));
+        match parse(&oversized, &BasicAuthConfig::new()) {
+            Err(Error::CredentialsTooLong { max_length }) => assert_eq!(max_length, 4096),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn header_is_normalized_before_parsing() {
+        let config = BasicAuthConfig::new();
+        // "alice:secret"
+        for header in &[
+            "Basic YWxpY2U6c2VjcmV0",
+            "Basic   YWxpY2U6c2VjcmV0",
+            "Basic\tYWxpY2U6c2VjcmV0",
+            "  basic YWxpY2U6c2VjcmV0  ",
+            "BASIC YWxpY2U6c2VjcmV0",
+            "bAsIc \t YWxpY2U6c2VjcmV0",
+        ] {
+            let auth_data = parse(header, &config).unwrap().unwrap();
+            assert_eq!(auth_data.user(), "alice", "{:?}", header);
+            assert_eq!(auth_data.password(), "secret", "{:?}", header);
+        }
+    }
+
+    #[test]
+    fn other_schemes_are_unsupported() {
+        match parse("Bearer YWxpY2U6c2VjcmV0", &BasicAuthConfig::new()) {
+            Err(Error::UnsupportedScheme { scheme }) => assert_eq!(scheme, "Bearer"),
+            other => panic!("unexpected {:?}", other),
+        }
+        match parse("BasicYWxpY2U6c2VjcmV0", &BasicAuthConfig::new()) {
+            Err(Error::UnsupportedScheme { .. }) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
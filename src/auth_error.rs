@@ -0,0 +1,173 @@
+use std::fmt::{
+    Display,
+    Formatter,
+};
+
+use actix_web::{
+    HttpResponse,
+    ResponseError,
+    http::{
+        StatusCode,
+        header::{
+            HeaderValue,
+            WWW_AUTHENTICATE,
+        },
+    },
+};
+
+use crate::{
+    error::Error,
+    response_map::{
+        FailureKind,
+        MappedResponse,
+        ResponseMap,
+    },
+};
+
+// Error returned by the `AuthControl` extractor. It gives a 401 (or a 403 when
+// a logged user is denied by the policy, or when no authentication scheme is
+// accepted, see `BasicAuthConfig::accept_basic`) by default, while leaving the cause
+// inspectable downstream: in a custom error handler (e.g. with
+// `actix_web::middleware::errhandlers`), use
+// `res.response().error().and_then(|e| e.as_error::<AuthError>())`. Handlers
+// can also take a `Result<AuthControl<U>, AuthError>` to deal with it directly.
+#[derive(Debug)]
+pub struct AuthError {
+    kind: FailureKind,
+    error: Box<Error>,
+    attempted_user: Option<String>,
+    // WWW-Authenticate header value sent along with a 401
+    challenge: Option<HeaderValue>,
+    // Boxed as it is rarely set, to keep the error small
+    mapped_response: Option<Box<MappedResponse>>,
+}
+impl AuthError {
+    pub(crate) fn new(kind: FailureKind, error: Error, challenge: Option<HeaderValue>) -> AuthError {
+        AuthError {
+            kind,
+            error: Box::new(error),
+            attempted_user: None,
+            challenge,
+            mapped_response: None,
+        }
+    }
+
+    pub(crate) fn with_attempted_user(mut self, attempted_user: Option<String>) -> AuthError {
+        self.attempted_user = attempted_user;
+        self
+    }
+
+    // Apply the override of the ResponseMap for this kind of failure, if any
+    pub(crate) fn mapped(mut self, response_map: Option<&ResponseMap>) -> AuthError {
+        self.mapped_response = response_map
+            .and_then(|response_map| response_map.get(self.kind))
+            .cloned()
+            .map(Box::new);
+        self
+    }
+
+    pub fn kind(&self) -> FailureKind {
+        self.kind
+    }
+
+    pub fn error(&self) -> &Error {
+        &self.error
+    }
+
+    // Username the client tried to log in with, when the credentials could
+    // be parsed. Only set if enabled with `BasicAuthConfig::expose_attempted_user`.
+    // It is never sent to the client. It is made up by the client and may
+    // hold any character, line breaks included: escape it before logging it,
+    // e.g. with `{:?}`.
+    pub fn attempted_user(&self) -> Option<&str> {
+        self.attempted_user.as_deref()
+    }
+}
+
+impl Display for AuthError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "{}", self.error)
+    }
+}
+
+impl ResponseError for AuthError {
+    fn status_code(&self) -> StatusCode {
+        let mapped_status = self.mapped_response.as_ref()
+            .and_then(|mapped_response| mapped_response.status);
+        match (mapped_status, self.kind.default_status(), &self.challenge) {
+            (Some(status), _, _) => status,
+            // A 401 must carry a challenge (RFC 7235, section 3.1): without
+            // any scheme to advertise, the request is merely refused
+            (None, StatusCode::UNAUTHORIZED, None) => StatusCode::FORBIDDEN,
+            (None, status, _) => status,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        let mut response = HttpResponse::build(status);
+        if let (StatusCode::UNAUTHORIZED, Some(challenge)) = (status, &self.challenge) {
+            response.header(WWW_AUTHENTICATE, challenge.clone());
+        }
+
+        let body = match self.mapped_response.as_ref().and_then(|m| m.body.as_ref()) {
+            Some(body) => body.clone(),
+            None => self.to_string(),
+        };
+        response
+            .content_type("text/plain; charset=utf-8")
+            .body(body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::challenge::basic_challenge;
+
+    #[test]
+    fn response_map_overrides_the_status_and_body() {
+        let response_map = ResponseMap::new()
+            .status(FailureKind::MalformedHeader, StatusCode::BAD_REQUEST)
+            .body(FailureKind::MalformedHeader, "Bad Authorization header");
+
+        let auth_error = AuthError::new(
+            FailureKind::MalformedHeader, Error::MalformedCredentials, Some(basic_challenge("Restricted"))
+        ).mapped(Some(&response_map));
+        let response = auth_error.error_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        // The challenge only goes along with a 401
+        assert!(response.headers().get(WWW_AUTHENTICATE).is_none());
+        match response.body().as_ref() {
+            Some(actix_web::body::Body::Bytes(bytes)) => assert_eq!(bytes, "Bad Authorization header"),
+            other => panic!("unexpected body {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unmapped_kinds_keep_the_default_response() {
+        let response_map = ResponseMap::new()
+            .status(FailureKind::MalformedHeader, StatusCode::BAD_REQUEST);
+
+        let auth_error = AuthError::new(
+            FailureKind::WrongPassword, Error::InvalidCredentials, Some(basic_challenge("Restricted"))
+        ).mapped(Some(&response_map));
+        let response = auth_error.error_response();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(response.headers().get(WWW_AUTHENTICATE).is_some());
+    }
+
+    #[test]
+    fn unauthorized_without_challenge_becomes_forbidden() {
+        let auth_error = AuthError::new(FailureKind::WrongPassword, Error::InvalidCredentials, None);
+        assert_eq!(auth_error.error_response().status(), StatusCode::FORBIDDEN);
+
+        // An explicit status is kept as-is
+        let response_map = ResponseMap::new()
+            .status(FailureKind::WrongPassword, StatusCode::UNAUTHORIZED);
+        let auth_error = AuthError::new(FailureKind::WrongPassword, Error::InvalidCredentials, None)
+            .mapped(Some(&response_map));
+        assert_eq!(auth_error.error_response().status(), StatusCode::UNAUTHORIZED);
+    }
+}
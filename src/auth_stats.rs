@@ -0,0 +1,174 @@
+use std::sync::atomic::{
+    AtomicU64,
+    Ordering,
+};
+
+use serde::{
+    Serialize,
+    Deserialize
+};
+
+use crate::{
+    auth_control::AuthResult,
+    response_map::FailureKind,
+};
+
+// Counts authentication attempts per outcome, e.g. to serve a `/stats`
+// endpoint without wiring a full metrics backend. Opt-in: add a
+// `Data<AuthStats>` to the actix app and `AuthControl` updates it on every
+// extraction.
+//
+// Counters are plain atomics, so recording never takes a lock. Like
+// `LoginTracker`, they are process-local and reset on restart.
+#[derive(Debug, Default)]
+pub struct AuthStats {
+    successes: AtomicU64,
+    failures: AtomicU64,
+    forbidden: AtomicU64,
+    anonymous: AtomicU64,
+    malformed: AtomicU64,
+    throttled: AtomicU64,
+    overloaded: AtomicU64,
+}
+
+// Point-in-time copy of the `AuthStats` counters
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default, Serialize, Deserialize)]
+pub struct AuthStatsSnapshot {
+    // Requests authenticated as a user and allowed by the policy
+    pub successes: u64,
+    // Requests with well-formed but rejected credentials (unknown user,
+    // wrong password, expired password), or sent over an insecure transport
+    pub failures: u64,
+    // Requests rejected by the user control policy, anonymous or not
+    pub forbidden: u64,
+    // Requests without credentials and allowed by the policy
+    pub anonymous: u64,
+    // Requests with an unparsable or empty-password authorization header
+    pub malformed: u64,
+    // Requests rejected by the `IpRateLimiter`
+    pub throttled: u64,
+    // Requests rejected by the `VerificationLimiter`
+    pub overloaded: u64,
+}
+
+impl AuthStats {
+    pub fn new() -> AuthStats {
+        AuthStats::default()
+    }
+
+    pub(crate) fn record_success(&self, auth_result: &AuthResult) {
+        let counter = match auth_result {
+            AuthResult::Anonymous => &self.anonymous,
+            AuthResult::LoggedUser { .. } => &self.successes,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_failure(&self, kind: FailureKind) {
+        let counter = match kind {
+            FailureKind::UnknownUser
+            | FailureKind::WrongPassword
+            | FailureKind::InsecureTransport
+            | FailureKind::PasswordExpired => &self.failures,
+            FailureKind::MalformedHeader
+            | FailureKind::EmptyPassword => &self.malformed,
+            FailureKind::Throttled => &self.throttled,
+            FailureKind::Overloaded => &self.overloaded,
+            FailureKind::AuthenticationRequired
+            | FailureKind::Forbidden => &self.forbidden,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Counters are read one after the other, so a snapshot taken under load
+    // might not add up exactly to the number of handled requests
+    pub fn snapshot(&self) -> AuthStatsSnapshot {
+        AuthStatsSnapshot {
+            successes: self.successes.load(Ordering::Relaxed),
+            failures: self.failures.load(Ordering::Relaxed),
+            forbidden: self.forbidden.load(Ordering::Relaxed),
+            anonymous: self.anonymous.load(Ordering::Relaxed),
+            malformed: self.malformed.load(Ordering::Relaxed),
+            throttled: self.throttled.load(Ordering::Relaxed),
+            overloaded: self.overloaded.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        test,
+        web,
+        App,
+        HttpResponse,
+        http::header::AUTHORIZATION,
+        web::Data,
+    };
+
+    use super::*;
+    use crate::{
+        auth_control::AuthControl,
+        auth_data::basic_auth_header,
+        htpasswd_database::HtpasswdDatabase,
+        ip_rate_limiter::IpRateLimiter,
+        user_control_policy::{
+            AnyLoggedUser,
+            Anyone,
+        },
+    };
+
+    async fn logged_user(_: AuthControl<AnyLoggedUser>) -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    async fn anyone(_: AuthControl<Anyone>) -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    #[actix_rt::test]
+    async fn requests_through_auth_control_are_counted_per_outcome() {
+        let mut htpasswd_database = HtpasswdDatabase::new();
+        htpasswd_database.add("alice", "secret").unwrap();
+        let auth_stats = Data::new(AuthStats::new());
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(Data::new(htpasswd_database))
+                .app_data(auth_stats.clone())
+                // The test requests have no peer address, so they all share
+                // the bucket of the unknown IPs: 6 requests, then throttled
+                .app_data(Data::new(IpRateLimiter::new(6, 0.0)))
+                .route("/", web::get().to(logged_user))
+                .route("/public", web::get().to(anyone))
+        ).await;
+        let with_header = |uri: &str, header: &str| test::TestRequest::get()
+            .uri(uri)
+            .header(AUTHORIZATION, header)
+            .to_request();
+
+        let requests = vec![
+            with_header("/", &basic_auth_header("alice", "secret")),
+            with_header("/", &basic_auth_header("alice", "wrong")),
+            with_header("/", &basic_auth_header("mallory", "secret")),
+            with_header("/", "Basic !!!"),
+            test::TestRequest::get().uri("/public").to_request(),
+            test::TestRequest::get().uri("/").to_request(),
+            with_header("/", &basic_auth_header("alice", "secret")),
+            test::TestRequest::get().uri("/public").to_request(),
+        ];
+        for req in requests {
+            test::call_service(&mut app, req).await;
+        }
+
+        assert_eq!(auth_stats.snapshot(), AuthStatsSnapshot {
+            successes: 1,
+            failures: 2,
+            forbidden: 1,
+            anonymous: 1,
+            malformed: 1,
+            throttled: 2,
+            overloaded: 0,
+        });
+    }
+}
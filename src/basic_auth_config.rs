@@ -0,0 +1,423 @@
+use std::net::{
+    IpAddr,
+    SocketAddr,
+};
+
+use actix_web::{
+    HttpRequest,
+    http::header::{
+        HeaderName,
+        HeaderValue,
+        FORWARDED,
+    },
+};
+
+use crate::challenge;
+
+const DEFAULT_REALM: &str = "Restricted";
+
+// Far more than any sensible "user:password" pair
+const DEFAULT_MAX_CREDENTIALS_LENGTH: usize = 4096;
+
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct BasicAuthConfig {
+    pub(crate) realm: String,
+    pub(crate) accept_basic: bool,
+    pub(crate) allow_empty_username: bool,
+    pub(crate) expose_attempted_user: bool,
+    pub(crate) require_secure_transport: bool,
+    pub(crate) trim_password: bool,
+    pub(crate) max_credentials_length: usize,
+    pub(crate) trusted_proxies: Vec<IpAddr>,
+    pub(crate) forwarded_for_header: HeaderName,
+    pub(crate) forwarded_proto_header: HeaderName,
+    pub(crate) proxy_user_header: Option<HeaderName>,
+}
+impl BasicAuthConfig {
+    pub fn new() -> BasicAuthConfig {
+        BasicAuthConfig::default()
+    }
+
+    // Realm advertised in the `WWW-Authenticate` challenge of 401 responses,
+    // shown by browsers in their login prompt. Defaults to "Restricted".
+    pub fn realm(mut self, realm: &str) -> BasicAuthConfig {
+        self.realm = realm.to_owned();
+        self
+    }
+
+    // Whether to accept credentials sent with the Basic scheme (the only
+    // one supported). Enabled by default.
+    //
+    // When disabled, Basic credentials are rejected with `UnsupportedScheme`,
+    // and the failures which would get a 401 get a 403 instead: a 401 must
+    // carry a challenge, and there is no scheme left to advertise. A
+    // `ResponseMap` status still takes precedence. The only way left to log in is
+    // then the `proxy_user_header`: this suits deployments where an upstream
+    // proxy handles the authentication and Basic credentials must never
+    // reach the app.
+    pub fn accept_basic(mut self, accept_basic: bool) -> BasicAuthConfig {
+        self.accept_basic = accept_basic;
+        self
+    }
+
+    // Accept credentials with an empty username (e.g. `:password`), for
+    // deployments which genuinely have an empty-username entry. Rejected with
+    // `EmptyUsername` by default.
+    pub fn allow_empty_username(mut self, allow_empty_username: bool) -> BasicAuthConfig {
+        self.allow_empty_username = allow_empty_username;
+        self
+    }
+
+    // Keep the username of failed login attempts in the `AuthError`
+    // (see `AuthError::attempted_user`), so that a middleware or an error
+    // handler can log e.g. "failed login for user X" to correlate attacks.
+    // The password is never kept. Disabled by default.
+    pub fn expose_attempted_user(mut self, expose_attempted_user: bool) -> BasicAuthConfig {
+        self.expose_attempted_user = expose_attempted_user;
+        self
+    }
+
+    // Reject Basic credentials received over plain HTTP, without checking
+    // them against the database. Disabled by default so as not to break
+    // local and development setups, but strongly recommended in production:
+    // Basic credentials are merely base64-encoded, so anyone on the network
+    // path can read them off a plaintext request.
+    //
+    // Over an insecure transport, requests with credentials (even malformed
+    // ones) get a 403 (`FailureKind::InsecureTransport`), and so do the
+    // anonymous requests denied by the policy, instead of a 401 challenge
+    // asking for credentials which would be refused anyway.
+    //
+    // The connection is secure if the server itself is bound with TLS. When
+    // TLS is terminated by a reverse proxy, the `forwarded_proto_header` is
+    // honored, but only for requests coming from one of the
+    // `trusted_proxies`.
+    pub fn require_secure_transport(mut self, require_secure_transport: bool) -> BasicAuthConfig {
+        self.require_secure_transport = require_secure_transport;
+        self
+    }
+
+    // Strip trailing whitespace from the submitted password before checking
+    // it against the database. Off by default (exact match).
+    //
+    // Beware: this reduces the effective entropy of the passwords, as
+    // `"secret"` and `"secret  "` are then accepted interchangeably.
+    pub fn trim_password(mut self, trim_password: bool) -> BasicAuthConfig {
+        self.trim_password = trim_password;
+        self
+    }
+
+    // Maximum length in bytes of the decoded "user:password" pair. Longer
+    // credentials are rejected with `CredentialsTooLong`, without being
+    // decoded at all. Defaults to 4096.
+    pub fn max_credentials_length(mut self, max_credentials_length: usize) -> BasicAuthConfig {
+        self.max_credentials_length = max_credentials_length;
+        self
+    }
+
+    // Addresses of the reverse proxies whose forwarded headers can be
+    // trusted. Requests from any other peer never have those headers taken
+    // into account.
+    pub fn trusted_proxies(mut self, trusted_proxies: Vec<IpAddr>) -> BasicAuthConfig {
+        self.trusted_proxies = trusted_proxies;
+        self
+    }
+
+    // Header in which the trusted proxies append the address of their peer,
+    // to find the client IP (see `IpRateLimiter`). Defaults to
+    // `X-Forwarded-For`. Only this header is read: any other forwarded header
+    // is passed along by the proxy as sent by the client, and can be
+    // anything. With `Forwarded`, the hops are taken from its "for="
+    // parameters.
+    pub fn forwarded_for_header(mut self, forwarded_for_header: HeaderName) -> BasicAuthConfig {
+        self.forwarded_for_header = forwarded_for_header;
+        self
+    }
+
+    // Header in which the trusted proxies tell the scheme of the connection
+    // of their client, to know whether it is secure (see
+    // `require_secure_transport`). Defaults to `X-Forwarded-Proto`. As for
+    // `forwarded_for_header`, only this header is read, and its last element
+    // is the one which counts. With `Forwarded`, the scheme is taken from its
+    // "proto=" parameter.
+    pub fn forwarded_proto_header(mut self, forwarded_proto_header: HeaderName) -> BasicAuthConfig {
+        self.forwarded_proto_header = forwarded_proto_header;
+        self
+    }
+
+    // Accept the username found in the given header (e.g.
+    // `X-Authenticated-User`) as already authenticated, without checking
+    // any credential against the database. The user policy still applies.
+    //
+    // Only requests coming from one of the `trusted_proxies` are concerned.
+    // This is meant for deployments where an upstream proxy terminates the
+    // authentication: the network path between the proxy and the app must
+    // be trusted, as anyone able to reach the app directly from a trusted
+    // address could otherwise log in as anybody.
+    pub fn proxy_user_header(mut self, proxy_user_header: HeaderName) -> BasicAuthConfig {
+        self.proxy_user_header = Some(proxy_user_header);
+        self
+    }
+
+    // Password to actually check against the stored hash
+    pub(crate) fn candidate_password<'a>(&self, password: &'a str) -> &'a str {
+        if self.trim_password {
+            password.trim_end()
+        } else {
+            password
+        }
+    }
+
+    // Only the accepted schemes are advertised
+    pub(crate) fn challenge(&self) -> Option<HeaderValue> {
+        if self.accept_basic {
+            Some(challenge::basic_challenge(&self.realm))
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn is_from_trusted_proxy(&self, req: &HttpRequest) -> bool {
+        req.peer_addr()
+            .map(|peer_addr| self.trusted_proxies.contains(&peer_addr.ip()))
+            .unwrap_or(false)
+    }
+
+    // Address of the client. For requests coming from a trusted proxy, it is
+    // the last hop of the `forwarded_for_header` which is not a trusted proxy
+    // itself: the hops on its left were sent by the client, and can be
+    // anything. None if that hop is missing or unparsable, rather than the
+    // address of the proxy, which is shared by all its clients.
+    pub(crate) fn client_ip(&self, req: &HttpRequest) -> Option<IpAddr> {
+        let peer_ip = req.peer_addr().map(|peer_addr| peer_addr.ip());
+        if !self.is_from_trusted_proxy(req) {
+            return peer_ip;
+        }
+
+        for hop in proxy_header_elements(req, &self.forwarded_for_header, "for").iter().rev() {
+            match parse_hop(hop) {
+                Some(ip) if self.trusted_proxies.contains(&ip) => continue,
+                ip => return ip,
+            }
+        }
+        None
+    }
+
+    pub(crate) fn is_secure_transport(&self, req: &HttpRequest) -> bool {
+        if req.app_config().secure() {
+            return true;
+        }
+        // Unlike `ConnectionInfo`, which blindly trusts any forwarded header,
+        // only the header set by the proxy is read
+        self.is_from_trusted_proxy(req)
+            && proxy_header_elements(req, &self.forwarded_proto_header, "proto").last()
+                .map(|scheme| scheme.eq_ignore_ascii_case("https"))
+                .unwrap_or(false)
+    }
+
+    // User authenticated upstream, if the request comes from a trusted proxy
+    // and carries the configured header
+    pub(crate) fn proxy_authenticated_user(&self, req: &HttpRequest) -> Option<String> {
+        let proxy_user_header = self.proxy_user_header.as_ref()?;
+        if !self.is_from_trusted_proxy(req) {
+            return None;
+        }
+
+        req.headers().get(proxy_user_header)
+            .and_then(|value| value.to_str().ok())
+            .filter(|user| !user.is_empty())
+            .map(str::to_owned)
+    }
+}
+
+// Comma-separated elements of a header set by a trusted proxy, in order.
+// For the `Forwarded` header, the value of the given parameter of each
+// element, e.g. "for". The HeaderMap of actix doesn't keep the order of
+// repeated header lines, so the elements are only known when the header comes
+// in a single line: several lines give no element at all.
+fn proxy_header_elements(req: &HttpRequest, name: &HeaderName, forwarded_parameter: &str) -> Vec<String> {
+    let mut values = req.headers().get_all(name);
+    let elements = match (values.next(), values.next()) {
+        (Some(value), None) => value.to_str().unwrap_or("").split(','),
+        _ => return Vec::new(),
+    };
+
+    if name != FORWARDED {
+        return elements.map(|element| element.trim().to_owned()).collect();
+    }
+    elements
+        .filter_map(|element| {
+            element.split(';')
+                .filter_map(|pair| pair.trim().split_once('='))
+                .find(|(name, _)| name.eq_ignore_ascii_case(forwarded_parameter))
+                .map(|(_, value)| value.trim_matches('"').to_owned())
+        })
+        .collect()
+}
+
+// A hop may carry a port, and IPv6 addresses may be in brackets
+fn parse_hop(hop: &str) -> Option<IpAddr> {
+    hop.parse::<IpAddr>().ok()
+        .or_else(|| hop.parse::<SocketAddr>().ok().map(|socket_addr| socket_addr.ip()))
+        .or_else(|| hop.strip_prefix('[')?.strip_suffix(']')?.parse::<IpAddr>().ok())
+}
+
+impl Default for BasicAuthConfig {
+    fn default() -> BasicAuthConfig {
+        BasicAuthConfig {
+            realm: DEFAULT_REALM.to_owned(),
+            accept_basic: true,
+            allow_empty_username: false,
+            expose_attempted_user: false,
+            require_secure_transport: false,
+            trim_password: false,
+            max_credentials_length: DEFAULT_MAX_CREDENTIALS_LENGTH,
+            trusted_proxies: Vec::new(),
+            forwarded_for_header: HeaderName::from_static("x-forwarded-for"),
+            forwarded_proto_header: HeaderName::from_static("x-forwarded-proto"),
+            proxy_user_header: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::*;
+
+    fn config() -> BasicAuthConfig {
+        BasicAuthConfig::new().trusted_proxies(vec!["10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap()])
+    }
+
+    fn from_proxy() -> TestRequest {
+        TestRequest::default().peer_addr("10.0.0.1:4242".parse().unwrap())
+    }
+
+    fn ip(ip: &str) -> Option<IpAddr> {
+        Some(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn forwarded_headers_are_ignored_from_untrusted_peers() {
+        let req = TestRequest::default()
+            .peer_addr("203.0.113.7:4242".parse().unwrap())
+            .header("X-Forwarded-For", "198.51.100.1")
+            .to_http_request();
+        assert_eq!(config().client_ip(&req), ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn client_ip_is_the_last_untrusted_hop() {
+        // The leftmost hops are made up by the client
+        let req = from_proxy()
+            .header("X-Forwarded-For", "1.2.3.4, 198.51.100.1, 10.0.0.2")
+            .to_http_request();
+        assert_eq!(config().client_ip(&req), ip("198.51.100.1"));
+
+        let req = from_proxy()
+            .header("X-Forwarded-For", "1.2.3.4, 198.51.100.1:1234")
+            .to_http_request();
+        assert_eq!(config().client_ip(&req), ip("198.51.100.1"));
+
+        let config = config().forwarded_for_header(FORWARDED);
+        let req = from_proxy()
+            .header("Forwarded", r#"for=1.2.3.4, for="[2001:db8::1]:1234";proto=https, for=10.0.0.2"#)
+            .header("X-Forwarded-For", "198.51.100.1")
+            .to_http_request();
+        assert_eq!(config.client_ip(&req), ip("2001:db8::1"));
+    }
+
+    #[test]
+    fn only_the_configured_header_is_read() {
+        // A `Forwarded` header sent by the client is passed along by the proxy
+        let req = from_proxy()
+            .header("Forwarded", "for=1.2.3.4")
+            .header("X-Forwarded-For", "198.51.100.1")
+            .to_http_request();
+        assert_eq!(config().client_ip(&req), ip("198.51.100.1"));
+
+        let req = from_proxy()
+            .header("Forwarded", "for=1.2.3.4")
+            .to_http_request();
+        assert_eq!(config().client_ip(&req), None);
+
+        let config = config().forwarded_for_header(HeaderName::from_static("x-real-ip"));
+        let req = from_proxy()
+            .header("X-Forwarded-For", "1.2.3.4")
+            .header("X-Real-IP", "198.51.100.1")
+            .to_http_request();
+        assert_eq!(config.client_ip(&req), ip("198.51.100.1"));
+    }
+
+    #[test]
+    fn client_ip_never_falls_back_to_the_proxy() {
+        for x_forwarded_for in &["", "10.0.0.2", "1.2.3.4, not-an-ip", "unknown, 10.0.0.2"] {
+            let req = from_proxy()
+                .header("X-Forwarded-For", *x_forwarded_for)
+                .to_http_request();
+            assert_eq!(config().client_ip(&req), None, "{:?}", x_forwarded_for);
+        }
+        assert_eq!(config().client_ip(&from_proxy().to_http_request()), None);
+
+        // The order of repeated lines is lost
+        let req = from_proxy()
+            .header("X-Forwarded-For", "1.2.3.4")
+            .header("X-Forwarded-For", "198.51.100.1")
+            .to_http_request();
+        assert_eq!(config().client_ip(&req), None);
+    }
+
+    #[test]
+    fn forwarded_proto_is_trusted_from_trusted_proxies() {
+        let req = from_proxy()
+            .header("X-Forwarded-Proto", "https")
+            .to_http_request();
+        assert!(config().is_secure_transport(&req));
+
+        // The proxy appended its own scheme to the one sent by the client
+        let req = from_proxy()
+            .header("X-Forwarded-Proto", "https, http")
+            .to_http_request();
+        assert!(!config().is_secure_transport(&req));
+
+        let config = config().forwarded_proto_header(FORWARDED);
+        let req = from_proxy()
+            .header("Forwarded", "for=198.51.100.1;proto=https")
+            .to_http_request();
+        assert!(config.is_secure_transport(&req));
+    }
+
+    #[test]
+    fn forwarded_proto_is_ignored_from_untrusted_peers() {
+        let req = TestRequest::default()
+            .peer_addr("203.0.113.7:4242".parse().unwrap())
+            .header("X-Forwarded-Proto", "https")
+            .header("Forwarded", "proto=https")
+            .to_http_request();
+        assert!(!config().is_secure_transport(&req));
+        assert!(!config().forwarded_proto_header(FORWARDED).is_secure_transport(&req));
+    }
+
+    #[test]
+    fn only_the_configured_proto_header_is_read() {
+        // Passed along by the proxy as sent by the client
+        let req = from_proxy()
+            .header("Forwarded", "proto=https")
+            .to_http_request();
+        assert!(!config().is_secure_transport(&req));
+
+        let req = from_proxy()
+            .header("Forwarded", "proto=https")
+            .header("X-Forwarded-Proto", "http")
+            .to_http_request();
+        assert!(!config().is_secure_transport(&req));
+
+        // The order of repeated lines is lost
+        let req = from_proxy()
+            .header("X-Forwarded-Proto", "http")
+            .header("X-Forwarded-Proto", "https")
+            .to_http_request();
+        assert!(!config().is_secure_transport(&req));
+    }
+}
@@ -0,0 +1,81 @@
+use actix_web::{
+    FromRequest,
+    HttpRequest,
+    dev::Payload,
+};
+use futures::future::{
+    ready,
+    Ready
+};
+
+use crate::{
+    auth_control,
+    auth_data::{
+        AuthData,
+        CredentialStatus,
+        SubmittedPassword,
+    },
+    auth_error::AuthError,
+    basic_auth_config::BasicAuthConfig,
+    error::Error,
+    response_map::{
+        FailureKind,
+        ResponseMap,
+    },
+};
+
+// Raw credentials of the Basic Authorization header, for handlers which check
+// them on their own (e.g. against an external service) instead of against an
+// HtpasswdDatabase. The header is parsed with the same rules as for
+// `AuthControl`, following the BasicAuthConfig of the app: a missing or
+// malformed header is rejected with a 401 (`FailureKind::MalformedHeader`, or
+// `EmptyPassword`). Take an `Option<BasicCredentials>` to allow anonymous
+// requests. The password is redacted from the Debug output, see
+// `SubmittedPassword`.
+#[derive(Clone, Debug)]
+pub struct BasicCredentials {
+    pub user: String,
+    password: SubmittedPassword,
+}
+impl BasicCredentials {
+    pub fn expose_password(&self) -> &str {
+        self.password.expose_password()
+    }
+}
+
+impl From<AuthData> for BasicCredentials {
+    fn from(auth_data: AuthData) -> BasicCredentials {
+        BasicCredentials {
+            user: auth_data.user,
+            password: auth_data.password,
+        }
+    }
+}
+
+impl FromRequest for BasicCredentials {
+    type Error = AuthError;
+    type Future = Ready<Result<Self, AuthError>>;
+    type Config = BasicAuthConfig;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let default_config = BasicAuthConfig::default();
+        let config = req.app_data::<Self::Config>().unwrap_or(&default_config);
+
+        let result = match CredentialStatus::from_headers(req.headers(), config) {
+            CredentialStatus::Present(auth_data) => Ok(BasicCredentials::from(auth_data)),
+            CredentialStatus::Absent => Err(AuthError::new(
+                FailureKind::MalformedHeader, Error::MissingCredentials, config.challenge()
+            )),
+            CredentialStatus::Malformed(error) => {
+                let kind = match error {
+                    Error::EmptyPassword => FailureKind::EmptyPassword,
+                    _ => FailureKind::MalformedHeader,
+                };
+                Err(AuthError::new(kind, error, config.challenge()))
+            }
+        };
+
+        ready(result.map_err(|auth_error| auth_error.mapped(auth_control::app_data::<ResponseMap>(req).as_ref())))
+    }
+}
+
@@ -0,0 +1,124 @@
+use std::{
+    sync::atomic::{
+        AtomicU64,
+        Ordering,
+    },
+    time::{
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+
+use actix_web::{
+    HttpResponse,
+    http::header::{
+        HeaderValue,
+        WWW_AUTHENTICATE,
+    },
+};
+
+use crate::basic_auth_config::BasicAuthConfig;
+
+// Makes the nonces unique even when the clock is too coarse to tell calls
+// apart
+static NONCE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+// Value of the WWW-Authenticate header asking for Basic credentials in the
+// given realm
+pub fn basic_challenge(realm: &str) -> HeaderValue {
+    let escaped_realm = realm.replace('\\', "\\\\").replace('"', "\\\"");
+    HeaderValue::from_str(&format!("Basic realm=\"{}\"", escaped_realm))
+        .unwrap_or_else(|_| HeaderValue::from_static("Basic"))
+}
+
+// Build a 401 response which makes the browser prompt for credentials again,
+// even if it has some cached (e.g. before a sensitive action).
+//
+// The challenge advertises the configured realm suffixed with a nonce which
+// changes on every call, so that the browser sees a brand new realm and
+// cannot reuse the credentials cached for the previous one. Browsers are not
+// all consistent there: some re-prompt only once, some keep sending cached
+// credentials preemptively, so this is a best-effort mechanism.
+pub fn force_reauthentication(config: &BasicAuthConfig) -> HttpResponse {
+    let time = SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_nanos())
+        .unwrap_or(0);
+    let count = NONCE_COUNTER.fetch_add(1, Ordering::Relaxed);
+
+    HttpResponse::Unauthorized()
+        .header(
+            WWW_AUTHENTICATE,
+            basic_challenge(&format!("{} #{:x}{:x}", config.realm, time, count))
+        )
+        .finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        test,
+        web,
+        App,
+        HttpRequest,
+        http::{
+            StatusCode,
+            header::AUTHORIZATION,
+        },
+        web::Data,
+    };
+
+    use super::*;
+    use crate::{
+        auth_control::AuthControl,
+        auth_data::basic_auth_header,
+        htpasswd_database::HtpasswdDatabase,
+        user_control_policy::AnyLoggedUser,
+    };
+
+    fn challenge_of(res: &HttpResponse) -> String {
+        res.headers().get(WWW_AUTHENTICATE).unwrap().to_str().unwrap().to_owned()
+    }
+
+    #[test]
+    fn every_reauthentication_advertises_a_new_realm() {
+        let config = BasicAuthConfig::new().realm("Intranet");
+        let challenges: Vec<String> = (0..100)
+            .map(|_| challenge_of(&force_reauthentication(&config)))
+            .collect();
+        for challenge in &challenges {
+            assert!(challenge.starts_with("Basic realm=\"Intranet #"), "{}", challenge);
+        }
+        let mut unique_challenges = challenges.clone();
+        unique_challenges.sort();
+        unique_challenges.dedup();
+        assert_eq!(unique_challenges.len(), challenges.len());
+    }
+
+    async fn sensitive_action(_: AuthControl<AnyLoggedUser>, req: HttpRequest) -> HttpResponse {
+        force_reauthentication(req.app_data::<BasicAuthConfig>().unwrap())
+    }
+
+    #[actix_rt::test]
+    async fn cached_credentials_get_a_new_challenge() {
+        let mut htpasswd_database = HtpasswdDatabase::new();
+        htpasswd_database.add("alice", "secret").unwrap();
+        let mut app = test::init_service(
+            App::new()
+                .app_data(Data::new(htpasswd_database))
+                .app_data(BasicAuthConfig::new())
+                .route("/", web::get().to(sensitive_action))
+        ).await;
+        let req = || test::TestRequest::get()
+            .header(AUTHORIZATION, basic_auth_header("alice", "secret"))
+            .to_request();
+
+        // The credentials are valid, yet refused with a challenge for another
+        // realm each time
+        let first = test::call_service(&mut app, req()).await;
+        let second = test::call_service(&mut app, req()).await;
+        assert_eq!(first.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(second.status(), StatusCode::UNAUTHORIZED);
+        assert_ne!(challenge_of(first.response()), challenge_of(second.response()));
+        assert_ne!(challenge_of(first.response()), "Basic realm=\"Restricted\"");
+    }
+}
@@ -0,0 +1,12 @@
+// Verifier for a hash format not supported out of the box (bespoke or legacy
+// schemes).
+//
+// Register it through `LoadOptions::custom_verifier`: when loading an
+// Htpasswd file, every entry whose hash starts with `prefix()` is then
+// handed to this verifier instead of the built-in schemes.
+pub trait CustomVerifier: Send + Sync {
+    fn prefix(&self) -> &str;
+
+    // `stored` is the full hash string from the file, prefix included
+    fn verify(&self, stored: &str, candidate: &str) -> bool;
+}
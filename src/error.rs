@@ -16,9 +16,14 @@ pub enum Error {
         scheme: String,
     },
     MissingScheme,
+    MissingCredentials,
     MalformedCredentials,
+    CredentialsTooLong {
+        max_length: usize,
+    },
     CannotExtractUsername,
     CannotExtractPassword,
+    EmptyUsername,
     EmptyPassword,
     CannotOpenHtpasswdFile {
         path_string: String,
@@ -28,6 +33,10 @@ pub enum Error {
         path_string: String,
         io_error: io::Error,
     },
+    CannotFetchHtpasswdUrl {
+        url: String,
+        reason: String,
+    },
     MalformedHtpasswdLine {
         path_string: String,
         line: usize,
@@ -36,9 +45,63 @@ pub enum Error {
         path_string: String,
         line: usize,
     },
+    InvalidHashLength {
+        path_string: String,
+        line: usize,
+        expected: usize,
+        got: usize,
+    },
+    UnsupportedHashScheme {
+        path_string: String,
+        scheme: String,
+        line: usize,
+    },
+    ExcessiveHashCost {
+        path_string: String,
+        line: usize,
+        reason: String,
+    },
     DuplicateUser {
         user: String,
     },
+    UnknownUser {
+        user: String,
+    },
+    DisallowedHashScheme {
+        user: String,
+        scheme: String,
+    },
+    EmptyHtpasswdDatabase {
+        path_string: String,
+    },
+    InvalidDigestLength {
+        user: String,
+        expected: usize,
+        got: usize,
+    },
+    InvalidUserHash {
+        user: String,
+        reason: String,
+    },
+    InvalidHashString {
+        reason: String,
+    },
+    CannotHashPassword {
+        scheme: String,
+        reason: String,
+    },
+    CannotWriteHtpasswdFile {
+        path_string: String,
+        io_error: io::Error,
+    },
+    InvalidCredentials,
+    InsufficientPrivileges,
+    InsecureTransport,
+    TooManyRequests,
+    TooManyVerifications,
+    PasswordExpired {
+        user: String,
+    },
 }
 impl Error {
     fn kind(&self) -> &'static str {
@@ -47,15 +110,36 @@ impl Error {
             CannotConvertHeaderToString => "CannotConvertHeaderToString",
             UnsupportedScheme { .. } => "UnsupportedScheme",
             MissingScheme => "MissingScheme",
+            MissingCredentials => "MissingCredentials",
             MalformedCredentials => "MalformedCredentials",
+            CredentialsTooLong { .. } => "CredentialsTooLong",
             CannotExtractUsername => "CannotExtractUsername",
             CannotExtractPassword => "CannotExtractPassword",
+            EmptyUsername => "EmptyUsername",
             EmptyPassword => "EmptyPassword",
             CannotOpenHtpasswdFile { .. } => "CannotOpenHtpasswdFile",
             CannotReadHtpasswdFile { .. } => "CannotReadHtpasswdFile",
+            CannotFetchHtpasswdUrl { .. } => "CannotFetchHtpasswdUrl",
             MalformedHtpasswdLine { .. } => "MalformedHtpasswdLine",
             InvalidPasswordString { .. } => "InvalidPasswordString",
+            InvalidHashLength { .. } => "InvalidHashLength",
+            UnsupportedHashScheme { .. } => "UnsupportedHashScheme",
+            ExcessiveHashCost { .. } => "ExcessiveHashCost",
             DuplicateUser { .. } => "DuplicateUser",
+            UnknownUser { .. } => "UnknownUser",
+            DisallowedHashScheme { .. } => "DisallowedHashScheme",
+            EmptyHtpasswdDatabase { .. } => "EmptyHtpasswdDatabase",
+            InvalidDigestLength { .. } => "InvalidDigestLength",
+            InvalidUserHash { .. } => "InvalidUserHash",
+            InvalidHashString { .. } => "InvalidHashString",
+            CannotHashPassword { .. } => "CannotHashPassword",
+            CannotWriteHtpasswdFile { .. } => "CannotWriteHtpasswdFile",
+            InvalidCredentials => "InvalidCredentials",
+            InsufficientPrivileges => "InsufficientPrivileges",
+            InsecureTransport => "InsecureTransport",
+            TooManyRequests => "TooManyRequests",
+            TooManyVerifications => "TooManyVerifications",
+            PasswordExpired { .. } => "PasswordExpired",
         }
     }
 }
@@ -70,14 +154,21 @@ impl Display for Error {
                 f, "Authorization header cannot be converted to string"
             ),
             UnsupportedScheme { scheme } => write!(
-                f, "Unsupported authentication scheme: expected \"Basic\", got \"{}\"",
+                f, "Unsupported authentication scheme \"{}\"",
                 scheme
             ),
             MissingScheme => write!(
                 f, "Authentication scheme is missing!"
             ),
+            MissingCredentials => write!(
+                f, "Credentials are required"
+            ),
             MalformedCredentials => write!(
-                f, "Encoded credentials are missing"
+                f, "Encoded credentials are missing or are not valid base64"
+            ),
+            CredentialsTooLong { max_length } => write!(
+                f, "Credentials longer than {} bytes",
+                max_length
             ),
             CannotExtractUsername => write!(
                 f, "Cannot extract username from credentials"
@@ -85,6 +176,9 @@ impl Display for Error {
             CannotExtractPassword => write!(
                 f, "Cannot extract password from credentials"
             ),
+            EmptyUsername => write!(
+                f, "Empty username isn't allowed"
+            ),
             EmptyPassword => write!(
                 f, "Empty password isn't allowed"
             ),
@@ -96,6 +190,10 @@ impl Display for Error {
                 f, "Cannot read Htpasswd file \"{}\": {}",
                 path_string, io_error
             ),
+            CannotFetchHtpasswdUrl { url, reason } => write!(
+                f, "Cannot fetch Htpasswd content from \"{}\": {}",
+                url, reason
+            ),
             MalformedHtpasswdLine { path_string, line } => write!(
                 f, "Invalid line in Htpasswd file \"{}\" at line {}",
                 path_string, line
@@ -104,10 +202,73 @@ impl Display for Error {
                 f, "Invalid base64 string for password in Htpasswd file \"{}\" at line {}",
                 path_string, line
             ),
+            InvalidHashLength { path_string, line, expected, got } => write!(
+                f, "Invalid hash length in Htpasswd file \"{}\" at line {}: expected {} bytes, got {}",
+                path_string, line, expected, got
+            ),
+            UnsupportedHashScheme { path_string, scheme, line } => write!(
+                f, "Unsupported hash scheme {} in Htpasswd file \"{}\" at line {} (a crate feature may need to be enabled)",
+                scheme, path_string, line
+            ),
+            ExcessiveHashCost { path_string, line, reason } => write!(
+                f, "Hash too costly to verify in Htpasswd file \"{}\" at line {}: {}",
+                path_string, line, reason
+            ),
             DuplicateUser { user } => write!(
                 f, "Duplicate user \"{}\"",
                 user
             ),
+            UnknownUser { user } => write!(
+                f, "Unknown user \"{}\"",
+                user
+            ),
+            DisallowedHashScheme { user, scheme } => write!(
+                f, "Disallowed {} hash scheme for user \"{}\"",
+                scheme, user
+            ),
+            EmptyHtpasswdDatabase { path_string } => write!(
+                f, "No user found in Htpasswd file \"{}\"",
+                path_string
+            ),
+            InvalidDigestLength { user, expected, got } => write!(
+                f, "Invalid digest length for user \"{}\": expected {} bytes, got {}",
+                user, expected, got
+            ),
+            InvalidUserHash { user, reason } => write!(
+                f, "Invalid password hash for user \"{}\": {}",
+                user, reason
+            ),
+            InvalidHashString { reason } => write!(
+                f, "Unrecognized or invalid password hash: {}",
+                reason
+            ),
+            CannotHashPassword { scheme, reason } => write!(
+                f, "Cannot hash password with the {} scheme: {}",
+                scheme, reason
+            ),
+            CannotWriteHtpasswdFile { path_string, io_error } => write!(
+                f, "Cannot write Htpasswd file \"{}\": {}",
+                path_string, io_error
+            ),
+            InvalidCredentials => write!(
+                f, "Unknown user or invalid password"
+            ),
+            InsufficientPrivileges => write!(
+                f, "Insufficient privileges to access this resource"
+            ),
+            InsecureTransport => write!(
+                f, "Credentials must be sent over a secure connection (HTTPS)"
+            ),
+            TooManyRequests => write!(
+                f, "Too many requests, try again later"
+            ),
+            TooManyVerifications => write!(
+                f, "Too many logins in progress, try again later"
+            ),
+            PasswordExpired { user } => write!(
+                f, "Password of user {:?} has expired",
+                user
+            ),
         }
     }
 }
@@ -119,3 +280,16 @@ impl Debug for Error {
 }
 
 impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn usernames_from_the_client_are_escaped() {
+        let error = Error::PasswordExpired {
+            user: "alice\r\nINFO forged line".to_owned(),
+        };
+        assert_eq!(error.to_string(), r#"Password of user "alice\r\nINFO forged line" has expired"#);
+    }
+}
@@ -0,0 +1,66 @@
+use std::{
+    future::Future,
+    sync::{
+        Arc,
+        Mutex,
+    },
+};
+
+use futures::future::{
+    FutureExt,
+    LocalBoxFuture,
+};
+
+use crate::password_hash::PasswordHash;
+
+type LookupFn = dyn Fn(&str) -> LocalBoxFuture<'static, Option<PasswordHash>> + Send + Sync;
+
+// Fetch the stored hash of a user on demand, instead of keeping the whole
+// HtpasswdDatabase in memory. Suits deployments where credentials live in an
+// external secret manager (Vault, AWS Secrets Manager...). When a
+// `Data<HashLookup>` (or a bare `HashLookup`) is added to the actix app,
+// `AuthControl` uses it in place of the `Data<HtpasswdDatabase>`.
+//
+// The closure is called on every authenticated request, so each request may
+// cost a network round-trip. Consider caching the fetched hashes in the
+// closure, keeping in mind that a cached hash outlives its revocation for
+// as long as it stays in the cache. An error while fetching should be mapped
+// to `None`, which is treated as an unknown user.
+//
+// Unknown users are verified against a dummy hash, as costly as the last
+// expensive (scrypt) hash returned by the closure, so that the response time
+// doesn't tell them apart from known users. Until the closure returns such a
+// hash, unknown users are only checked against a cheap dummy digest.
+#[derive(Clone)]
+pub struct HashLookup {
+    lookup: Arc<LookupFn>,
+    dummy_hash: Arc<Mutex<Option<PasswordHash>>>,
+}
+impl HashLookup {
+    pub fn new<F, R>(lookup: F) -> HashLookup
+    where
+        F: Fn(&str) -> R + Send + Sync + 'static,
+        R: Future<Output = Option<PasswordHash>> + 'static,
+    {
+        HashLookup {
+            lookup: Arc::new(move |user| lookup(user).boxed_local()),
+            dummy_hash: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub(crate) fn lookup(&self, user: &str) -> LocalBoxFuture<'static, Option<PasswordHash>> {
+        let dummy_hash = self.dummy_hash.clone();
+        (self.lookup)(user)
+            .map(move |password_hash| {
+                if let Some(dummy) = password_hash.as_ref().and_then(PasswordHash::dummy) {
+                    *dummy_hash.lock().unwrap() = Some(dummy);
+                }
+                password_hash
+            })
+            .boxed_local()
+    }
+
+    pub(crate) fn dummy_hash(&self) -> Option<PasswordHash> {
+        self.dummy_hash.lock().unwrap().clone()
+    }
+}
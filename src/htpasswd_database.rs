@@ -1,11 +1,22 @@
 use std::{
-    collections::HashMap,
-    fs::File,
-    path::Path,
+    collections::{
+        HashMap,
+        HashSet,
+    },
+    fs::{
+        self,
+        File,
+        OpenOptions,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+    time::SystemTime,
     convert::TryFrom,
     io::{
-        BufRead,
-        BufReader
+        Read,
+        Write,
     },
     fmt::{
         Display,
@@ -13,10 +24,6 @@ use std::{
     }
 };
 
-use sha1::{
-    Digest,
-    Sha1
-};
 use serde::{
     Serialize,
     Deserialize
@@ -24,120 +31,856 @@ use serde::{
 
 use crate::{
     auth_data::AuthData,
-    error::Error
+    basic_auth_config::BasicAuthConfig,
+    error::Error,
+    htpasswd_entry::{
+        self,
+        HtpasswdEntry,
+        UserEntry
+    },
+    load_options::LoadOptions,
+    merged_reloadable_database::ConflictStrategy,
+    password_hash::{
+        self,
+        HashScheme,
+        PasswordHash
+    },
 };
 
-#[derive(Clone, Eq, PartialEq, Debug, Default, Serialize, Deserialize)]
+// Maximum size in bytes of an Htpasswd file fetched over HTTP(S)
+#[cfg(feature = "url")]
+const MAX_FETCHED_CONTENT_SIZE: usize = 16 * 1024 * 1024;
+
+#[derive(Clone, Eq, Debug, Default, Serialize, Deserialize)]
+#[serde(from = "SerializedDatabase")]
 pub struct HtpasswdDatabase {
-    registered_users: HashMap<String, Vec<u8>>,
+    // A user usually has a single hash, but may have several of them while
+    // migrating from a scheme to another (see `LoadOptions`)
+    registered_users: HashMap<String, Vec<PasswordHash>>,
+    // Crate extension: Htpasswd files carry no expiry, so it is set
+    // programmatically, and checked only once the password is known to be
+    // correct
+    expiries: HashMap<String, SystemTime>,
+    // Derived from the hashes whenever they change, rather than looked for
+    // on each login, see `dummy_hash`
+    #[serde(skip)]
+    dummy_hash: Option<PasswordHash>,
+}
+
+#[derive(Deserialize)]
+struct SerializedDatabase {
+    registered_users: HashMap<String, Vec<PasswordHash>>,
+    #[serde(default)]
+    expiries: HashMap<String, SystemTime>,
+}
+
+impl From<SerializedDatabase> for HtpasswdDatabase {
+    fn from(serialized: SerializedDatabase) -> HtpasswdDatabase {
+        HtpasswdDatabase::with_dummy_hash(serialized.registered_users, serialized.expiries)
+    }
+}
+
+// The dummy hash is a cache
+impl PartialEq for HtpasswdDatabase {
+    fn eq(&self, other: &HtpasswdDatabase) -> bool {
+        self.registered_users == other.registered_users && self.expiries == other.expiries
+    }
 }
 impl HtpasswdDatabase {
     pub fn new() -> HtpasswdDatabase {
-        HtpasswdDatabase { registered_users: HashMap::new() }
+        HtpasswdDatabase::default()
     }
 
+    // Register a new user, with the SHA-1 hash of the password. Strict: fails
+    // with `DuplicateUser` if the user is already there, e.g. from the loaded
+    // file. See `upsert` to override an entry, and `update_password` to only
+    // change the password of an existing user.
     pub fn add(&mut self, user: &str, password: &str) -> Result<(), Error> {
         if self.registered_users.contains_key(user) {
             return Err(Error::DuplicateUser {
                 user: user.to_owned(),
             });
         }
-        let mut sha1_hasher = Sha1::new();
-        sha1_hasher.input(password);
-        let sha1_password = sha1_hasher.result().to_vec();
+        self.registered_users.insert(user.to_owned(), vec![PasswordHash::sha1(password)]);
+        Ok(())
+    }
 
-        self.registered_users.insert(user.to_owned(), sha1_password);
+    // Register the user with the given hash, replacing all the hashes (and
+    // the expiry) it had if it was already there
+    pub fn upsert(&mut self, user: &str, hash: PasswordHash) {
+        self.expiries.remove(user);
+        self.registered_users.insert(user.to_owned(), vec![hash]);
+        self.refresh_dummy_hash();
+    }
+
+    // Replace all the hashes (and the expiry) of an existing user. Fails with
+    // `UnknownUser` instead of adding the user.
+    pub fn update_password(&mut self, user: &str, hash: PasswordHash) -> Result<(), Error> {
+        if !self.contains(user) {
+            return Err(Error::UnknownUser {
+                user: user.to_owned(),
+            });
+        }
+        self.upsert(user, hash);
+        Ok(())
+    }
+
+    // Same as `add`, but the password is no longer accepted after `expiry`
+    pub fn add_with_expiry(&mut self, user: &str, password: &str, expiry: SystemTime) -> Result<(), Error> {
+        self.add(user, password)?;
+        self.expiries.insert(user.to_owned(), expiry);
         Ok(())
     }
 
-    pub(crate) fn is_valid(&self, auth_data: &AuthData) -> bool {
-        if !self.registered_users.contains_key(&auth_data.user) {
+    // Set (or clear, with `None`) the expiry of the password of a registered
+    // user. Returns false, without storing anything, for an unknown user.
+    pub fn set_expiry(&mut self, user: &str, expiry: Option<SystemTime>) -> bool {
+        if !self.contains(user) {
             return false;
         }
+        match expiry {
+            Some(expiry) => self.expiries.insert(user.to_owned(), expiry),
+            None => self.expiries.remove(user),
+        };
+        true
+    }
+
+    pub fn expiry_of(&self, user: &str) -> Option<SystemTime> {
+        self.expiries.get(user).copied()
+    }
+
+    pub(crate) fn is_expired(&self, user: &str) -> bool {
+        self.expiry_of(user)
+            .is_some_and(|expiry| expiry <= SystemTime::now())
+    }
 
-        // At the moment, only SHA-1 hashed passwords are supported in the
-        // Htpasswd file. Thus, SHA-1 of the supplied password is computed here.
-        let mut sha1_hasher = Sha1::new();
-        sha1_hasher.input(&auth_data.password);
-        let sha1_password = sha1_hasher.result().to_vec();
+    // Check every stored entry against its scheme, and report all the
+    // problems found instead of stopping at the first one. Meant to be run
+    // once after loading, to fail fast at startup.
+    pub fn validate(&self) -> Result<(), Vec<Error>> {
+        let errors: Vec<Error> = self.registered_users.iter()
+            .flat_map(|(user, password_hashes)| {
+                password_hashes.iter()
+                    .filter_map(move |password_hash| password_hash.validate(user).err())
+            })
+            .collect();
 
-        &sha1_password == self.registered_users.get(&auth_data.user).unwrap()
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
     }
-}
 
-impl TryFrom<&Path> for HtpasswdDatabase {
-    type Error = Error;
+    pub fn load(htpasswd_file_path: &Path, options: &LoadOptions) -> Result<HtpasswdDatabase, Error> {
+        HtpasswdDatabase::load_with_warnings(htpasswd_file_path, options)
+            .map(|(htpasswd_database, _)| htpasswd_database)
+    }
 
-    fn try_from(htpasswd_file_path: &Path) -> Result<Self, Self::Error> {
+    // Load the database and check that it is actually usable: the file loads
+    // with the given options (including the disallowed schemes), holds at
+    // least one user, and passes `validate`. Meant as the single fail-fast
+    // call at startup, e.g.
+    // `HtpasswdDatabase::load_and_validate(path, &options).expect("Invalid Htpasswd file")`,
+    // so that the server never starts with a broken credential store.
+    pub fn load_and_validate(
+        htpasswd_file_path: &Path,
+        options: &LoadOptions
+    ) -> Result<HtpasswdDatabase, Vec<Error>> {
+        let htpasswd_database = HtpasswdDatabase::load(htpasswd_file_path, options)
+            .map_err(|error| vec![error])?;
+
+        if htpasswd_database.registered_users.is_empty() {
+            return Err(vec![Error::EmptyHtpasswdDatabase {
+                path_string: htpasswd_file_path.to_string_lossy().to_string(),
+            }]);
+        }
+        htpasswd_database.validate()?;
+
+        Ok(htpasswd_database)
+    }
+
+    // Same as `load`, but also returns the errors of the lines skipped with
+    // `LoadOptions::skip_invalid_lines` (always empty without that option)
+    pub fn load_with_warnings(
+        htpasswd_file_path: &Path,
+        options: &LoadOptions
+    ) -> Result<(HtpasswdDatabase, Vec<Error>), Error> {
         let path_string = htpasswd_file_path.to_string_lossy().to_string();
 
-        let file = File::open(htpasswd_file_path)
+        let mut file = File::open(htpasswd_file_path)
             .map_err(|io_error| Error::CannotOpenHtpasswdFile {
                 path_string: path_string.clone(),
                 io_error,
             })?;
 
-        let reader = BufReader::new(file);
+        let mut content = String::new();
+        file.read_to_string(&mut content)
+            .map_err(|io_error| Error::CannotReadHtpasswdFile {
+                path_string: path_string.clone(),
+                io_error,
+            })?;
 
-        // Create the internal hashmap which will be used to store the
-        // recognized credentials
-        let mut registered_users = HashMap::new();
+        let (entries, skipped) = htpasswd_entry::parse_entries_with(&content, &path_string, options)?;
+        HtpasswdDatabase::from_entries(entries, options)
+            .map(|htpasswd_database| (htpasswd_database, skipped))
+    }
 
-        for (i, line_res) in reader.lines().enumerate() {
+    // Fetch the content of an Htpasswd file over HTTP(S), e.g. from a central
+    // configuration service, and parse it like a local file. TLS certificates
+    // are verified against the webpki root certificates.
+    #[cfg(feature = "url")]
+    pub async fn from_url(url: &str, options: &LoadOptions) -> Result<HtpasswdDatabase, Error> {
+        let fetch_error = |reason: String| Error::CannotFetchHtpasswdUrl {
+            url: url.to_owned(),
+            reason,
+        };
 
-            let owned_line = line_res
-                .map_err(|io_error| Error::CannotReadHtpasswdFile {
-                    path_string: path_string.clone(),
-                    io_error,
-                })?;
+        let mut response = awc::Client::default().get(url).send().await
+            .map_err(|send_error| fetch_error(send_error.to_string()))?;
+        if !response.status().is_success() {
+            return Err(fetch_error(format!("HTTP status {}", response.status())));
+        }
 
-            let line = owned_line.trim();
-            if line.is_empty() {
-                continue;
-            }
+        let body = response.body().limit(MAX_FETCHED_CONTENT_SIZE).await
+            .map_err(|payload_error| fetch_error(payload_error.to_string()))?;
+        let content = String::from_utf8(body.to_vec())
+            .map_err(|utf8_error| fetch_error(utf8_error.to_string()))?;
+
+        // Skipped lines, if any, are only logged
+        let (entries, _) = htpasswd_entry::parse_entries_with(&content, url, options)?;
+        HtpasswdDatabase::from_entries(entries, options)
+    }
 
-            // Cut the htpasswd line on the ":{SHA}" string, which splits
-            // the username from the base64 representation of the password sha1.
-            // At the moment, only SHA-1 hashed passwords are supported in the
-            // Htpasswd file.
-            let parts: Vec<&str> = line.split(":{SHA}").collect();
-            if parts.len() != 2 {
-                return Err(Error::MalformedHtpasswdLine {
-                    path_string: path_string.clone(),
-                    line: i,
+    pub(crate) fn from_entries(
+        entries: Vec<HtpasswdEntry>,
+        options: &LoadOptions
+    ) -> Result<HtpasswdDatabase, Error> {
+        // Create the internal hashmap which will be used to store the
+        // recognized credentials
+        let mut registered_users: HashMap<String, Vec<PasswordHash>> = HashMap::new();
+
+        for entry in entries {
+            // Check for duplicated credentials, unless several hashes per
+            // user are explicitly allowed
+            if registered_users.contains_key(&entry.user) && !options.allow_multiple_hashes {
+                return Err(Error::DuplicateUser {
+                    user: entry.user,
+                });
+            }
+            if options.disallowed_schemes.contains(&entry.hash.scheme()) {
+                return Err(Error::DisallowedHashScheme {
+                    user: entry.user,
+                    scheme: entry.hash.scheme().to_string(),
                 });
             }
 
-            let user = parts[0];
-            let base64_sha1_password = parts[1];
+            registered_users.entry(entry.user).or_default().push(entry.hash);
+        }
+
+        Ok(HtpasswdDatabase::with_dummy_hash(registered_users, HashMap::new()))
+    }
+
+    fn with_dummy_hash(
+        registered_users: HashMap<String, Vec<PasswordHash>>,
+        expiries: HashMap<String, SystemTime>
+    ) -> HtpasswdDatabase {
+        let mut htpasswd_database = HtpasswdDatabase {
+            registered_users,
+            expiries,
+            dummy_hash: None,
+        };
+        htpasswd_database.refresh_dummy_hash();
+        htpasswd_database
+    }
+
+    // To be called whenever hashes are replaced or removed
+    fn refresh_dummy_hash(&mut self) {
+        self.dummy_hash = self.registered_users.values()
+            .flatten()
+            .find_map(PasswordHash::dummy);
+    }
 
-            let sha1_password =
-                base64::decode(base64_sha1_password)
-                    .map_err(|_| Error::InvalidPasswordString {
-                        path_string: path_string.clone(),
-                        line: i,
-                    })?;
+    // Write the database as an Htpasswd file, one line per stored hash, with
+    // the users sorted by name. The file is replaced atomically if it already
+    // exists: the content goes to a temporary file in the same directory,
+    // which is then renamed over it, so that a concurrent load never sees a
+    // partial file. The replaced file keeps its permissions; a new one is
+    // only readable and writable by its owner (on Unix).
+    pub fn save(&self, htpasswd_file_path: &Path) -> Result<(), Error> {
+        let write_error = |io_error| Error::CannotWriteHtpasswdFile {
+            path_string: htpasswd_file_path.to_string_lossy().to_string(),
+            io_error,
+        };
 
-            // Check for duplicated credentials in the Htpasswd file
-            if registered_users.contains_key(user) {
+        let temp_file_path = temp_file_path(htpasswd_file_path);
+        let written = write_private_file(&temp_file_path, self.to_htpasswd_string().as_bytes())
+            .and_then(|_| match fs::metadata(htpasswd_file_path) {
+                Ok(metadata) => fs::set_permissions(&temp_file_path, metadata.permissions()),
+                Err(_) => Ok(()),
+            })
+            .and_then(|_| fs::rename(&temp_file_path, htpasswd_file_path));
+        if let Err(io_error) = written {
+            let _ = fs::remove_file(&temp_file_path);
+            return Err(write_error(io_error));
+        }
+        Ok(())
+    }
+
+    // Hash each plaintext password with the given scheme and write the
+    // resulting Htpasswd file in one go, e.g. in provisioning scripts.
+    // Duplicate users are rejected, like in `add`.
+    pub fn generate(entries: &[(&str, &str)], scheme: HashScheme, htpasswd_file_path: &Path) -> Result<(), Error> {
+        let mut htpasswd_database = HtpasswdDatabase::new();
+        for (user, password) in entries {
+            if htpasswd_database.contains(user) {
                 return Err(Error::DuplicateUser {
-                    user: user.to_owned(),
+                    user: (*user).to_owned(),
                 });
             }
+            let password_hash = password_hash::hash_password(password, scheme)?;
+            htpasswd_database.registered_users.insert((*user).to_owned(), vec![password_hash]);
+        }
+        htpasswd_database.save(htpasswd_file_path)
+    }
+
+    fn to_htpasswd_string(&self) -> String {
+        let mut users: Vec<&String> = self.registered_users.keys().collect();
+        users.sort();
 
-            registered_users.insert(user.to_owned(), sha1_password);
+        let mut content = String::new();
+        for user in users {
+            for password_hash in &self.registered_users[user] {
+                content.push_str(&format!("{}:{}\n", user, password_hash));
+            }
         }
+        content
+    }
 
-        Ok(HtpasswdDatabase { registered_users })
+    // Add the users of another database, e.g. loaded from another file. The
+    // strategy decides what happens to the users present in both. On
+    // failure, the database is left untouched.
+    pub fn merge(&mut self, other: HtpasswdDatabase, conflict_strategy: ConflictStrategy) -> Result<(), Error> {
+        if conflict_strategy == ConflictStrategy::Fail {
+            if let Some(user) = other.registered_users.keys().find(|user| self.contains(user)) {
+                return Err(Error::DuplicateUser { user: user.clone() });
+            }
+        }
+
+        for (user, password_hashes) in other.registered_users {
+            if conflict_strategy == ConflictStrategy::FirstWins && self.contains(&user) {
+                continue;
+            }
+            // The expiry goes along with the password it applies to
+            match other.expiries.get(&user) {
+                Some(expiry) => self.expiries.insert(user.clone(), *expiry),
+                None => self.expiries.remove(&user),
+            };
+            self.registered_users.insert(user, password_hashes);
+        }
+        self.refresh_dummy_hash();
+        Ok(())
+    }
+
+    // Copy of the database keeping only the given users, as if it were loaded
+    // from a file filtered down to them. Useful to share one Htpasswd file
+    // between services which should each accept only some of the users.
+    pub fn restrict_to(&self, users: &HashSet<String>) -> HtpasswdDatabase {
+        HtpasswdDatabase::with_dummy_hash(
+            self.registered_users.iter()
+                .filter(|(user, _)| users.contains(*user))
+                .map(|(user, password_hashes)| (user.clone(), password_hashes.clone()))
+                .collect(),
+            self.expiries.iter()
+                .filter(|(user, _)| users.contains(*user))
+                .map(|(user, expiry)| (user.clone(), *expiry))
+                .collect(),
+        )
+    }
+
+    pub fn contains(&self, user: &str) -> bool {
+        self.registered_users.contains_key(user)
+    }
+
+    // Schemes of the hashes stored for the user, empty for an unknown user
+    pub fn schemes_of(&self, user: &str) -> Vec<HashScheme> {
+        self.get(user).iter().map(PasswordHash::scheme).collect()
+    }
+
+    // Users with at least one hash of a weak scheme, sorted by name, e.g. to
+    // list the accounts whose password should be reset. While migrating with
+    // several hashes per user, a user stays weak as long as the old hash is
+    // kept, as it is still accepted.
+    pub fn weak_users(&self) -> Vec<&str> {
+        let mut weak_users: Vec<&str> = self.registered_users.iter()
+            .filter(|(_, password_hashes)| {
+                password_hashes.iter().any(|password_hash| password_hash.scheme().is_weak())
+            })
+            .map(|(user, _)| user.as_str())
+            .collect();
+        weak_users.sort_unstable();
+        weak_users
+    }
+
+    pub(crate) fn get(&self, user: &str) -> &[PasswordHash] {
+        self.registered_users.get(user).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    // Dummy hash to verify the password of unknown users against, as costly
+    // as the expensive hashes of the database (see `PasswordHash::dummy`).
+    // None if there is no such hash. Computed when the hashes change, so
+    // that looking it up takes the same time whatever the size of the
+    // database.
+    pub(crate) fn dummy_hash(&self) -> Option<PasswordHash> {
+        self.dummy_hash.clone()
+    }
+
+    pub(crate) fn is_valid(&self, auth_data: &AuthData, config: &BasicAuthConfig) -> bool {
+        let password = config.candidate_password(auth_data.password());
+        let stored_hashes = self.get(&auth_data.user);
+        let dummy_hash = if stored_hashes.is_empty() { self.dummy_hash() } else { None };
+
+        // Always run the verification, even for unknown users, to avoid
+        // leaking whether a user exists through response timing
+        password_hash::verify_any(stored_hashes, dummy_hash.as_ref(), password)
+            && !self.is_expired(&auth_data.user)
+    }
+}
+
+// Sibling of the file, so that the rename stays on the same filesystem
+fn temp_file_path(htpasswd_file_path: &Path) -> PathBuf {
+    let file_name = htpasswd_file_path.file_name()
+        .map(|file_name| file_name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    htpasswd_file_path.with_file_name(format!(".{}.tmp-{}", file_name, std::process::id()))
+}
+
+// Create the file from scratch (a leftover of a failed save is removed
+// first), never following a symlink planted in its place
+fn write_private_file(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    let _ = fs::remove_file(path);
+
+    let mut open_options = OpenOptions::new();
+    open_options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        open_options.mode(0o600);
+    }
+
+    let mut file = open_options.open(path)?;
+    file.write_all(content)?;
+    file.sync_all()
+}
+
+impl TryFrom<&Path> for HtpasswdDatabase {
+    type Error = Error;
+
+    fn try_from(htpasswd_file_path: &Path) -> Result<Self, Self::Error> {
+        HtpasswdDatabase::load(htpasswd_file_path, &LoadOptions::default())
+    }
+}
+
+// Build the database from users listed in a configuration file. Duplicate
+// users are rejected, like in Htpasswd files.
+impl TryFrom<Vec<UserEntry>> for HtpasswdDatabase {
+    type Error = Error;
+
+    fn try_from(user_entries: Vec<UserEntry>) -> Result<Self, Self::Error> {
+        let entries = user_entries.into_iter()
+            .map(|user_entry| HtpasswdEntry {
+                user: user_entry.name,
+                hash: user_entry.hash,
+                line: None,
+            })
+            .collect();
+        HtpasswdDatabase::from_entries(entries, &LoadOptions::default())
     }
 }
 
 impl Display for HtpasswdDatabase {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
         write!(f, "--- Htpasswd Database ---")?;
-        for (user, password) in self.registered_users.iter() {
-            write!(f, "{}:{{SHA}}{}", user, base64::encode(password))?;
+        for (user, password_hashes) in self.registered_users.iter() {
+            for password_hash in password_hashes {
+                write!(f, "{}:{}", user, password_hash)?;
+            }
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        auth_data::basic_auth_header,
+        custom_verifier::CustomVerifier,
+    };
+
+    fn auth_data(user: &str, password: &str, config: &BasicAuthConfig) -> AuthData {
+        AuthData::from_header_value(Some(&basic_auth_header(user, password)), config)
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn trailing_whitespace_is_only_accepted_with_trim_password() {
+        let mut htpasswd_database = HtpasswdDatabase::new();
+        htpasswd_database.add("alice", "secret").unwrap();
+
+        let exact = BasicAuthConfig::new();
+        assert!(htpasswd_database.is_valid(&auth_data("alice", "secret", &exact), &exact));
+        assert!(!htpasswd_database.is_valid(&auth_data("alice", "secret  ", &exact), &exact));
+        assert!(!htpasswd_database.is_valid(&auth_data("alice", "secret\t", &exact), &exact));
+
+        let trimming = BasicAuthConfig::new().trim_password(true);
+        assert!(htpasswd_database.is_valid(&auth_data("alice", "secret", &trimming), &trimming));
+        assert!(htpasswd_database.is_valid(&auth_data("alice", "secret  ", &trimming), &trimming));
+        assert!(htpasswd_database.is_valid(&auth_data("alice", "secret\t", &trimming), &trimming));
+        // Only trailing whitespace is trimmed
+        assert!(!htpasswd_database.is_valid(&auth_data("alice", " secret", &trimming), &trimming));
+    }
+
+    #[test]
+    fn restrict_to_excludes_the_other_users() {
+        let mut htpasswd_database = HtpasswdDatabase::new();
+        htpasswd_database.add("alice", "alice-secret").unwrap();
+        htpasswd_database.add("bob", "bob-secret").unwrap();
+        htpasswd_database.set_expiry("bob", Some(SystemTime::now()));
+
+        let users: HashSet<String> = vec!["alice".to_owned(), "carol".to_owned()].into_iter().collect();
+        let restricted = htpasswd_database.restrict_to(&users);
+
+        let config = BasicAuthConfig::new();
+        assert!(restricted.contains("alice"));
+        assert!(!restricted.contains("bob"));
+        assert!(!restricted.contains("carol"));
+        assert!(restricted.is_valid(&auth_data("alice", "alice-secret", &config), &config));
+        assert!(!restricted.is_valid(&auth_data("bob", "bob-secret", &config), &config));
+        assert_eq!(restricted.expiry_of("bob"), None);
+    }
+
+    #[test]
+    fn expired_passwords_are_refused() {
+        let hour = std::time::Duration::from_secs(3600);
+        let mut htpasswd_database = HtpasswdDatabase::new();
+        htpasswd_database.add_with_expiry("expired", "secret", SystemTime::now() - hour).unwrap();
+        htpasswd_database.add_with_expiry("valid", "secret", SystemTime::now() + hour).unwrap();
+
+        let config = BasicAuthConfig::new();
+        assert!(htpasswd_database.is_expired("expired"));
+        assert!(!htpasswd_database.is_expired("valid"));
+        assert!(!htpasswd_database.is_valid(&auth_data("expired", "secret", &config), &config));
+        assert!(htpasswd_database.is_valid(&auth_data("valid", "secret", &config), &config));
+
+        // Clearing the expiry makes the password valid again
+        assert!(htpasswd_database.set_expiry("expired", None));
+        assert!(htpasswd_database.is_valid(&auth_data("expired", "secret", &config), &config));
+        assert!(!htpasswd_database.set_expiry("unknown", Some(SystemTime::now())));
+    }
+
+    #[test]
+    fn add_rejects_an_existing_user() {
+        let mut htpasswd_database = HtpasswdDatabase::new();
+        htpasswd_database.add("alice", "first").unwrap();
+        match htpasswd_database.add("alice", "second") {
+            Err(Error::DuplicateUser { user }) => assert_eq!(user, "alice"),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        // The first password is kept
+        let config = BasicAuthConfig::new();
+        assert!(htpasswd_database.is_valid(&auth_data("alice", "first", &config), &config));
+        assert!(!htpasswd_database.is_valid(&auth_data("alice", "second", &config), &config));
+    }
+
+    #[test]
+    fn upsert_replaces_the_hashes_and_the_expiry() {
+        let loaded = format!("alice:{}\nalice:{}\n", PasswordHash::sha1("old"), PasswordHash::sha1("older"));
+        let options = LoadOptions::new().allow_multiple_hashes(true);
+        let (entries, _) = htpasswd_entry::parse_entries_with(&loaded, "<test>", &options).unwrap();
+        let mut htpasswd_database = HtpasswdDatabase::from_entries(entries, &options).unwrap();
+        htpasswd_database.set_expiry("alice", Some(SystemTime::now()));
+
+        htpasswd_database.upsert("alice", PasswordHash::sha1("new"));
+        htpasswd_database.upsert("bob", PasswordHash::sha1("bob-secret"));
+
+        let config = BasicAuthConfig::new();
+        assert_eq!(htpasswd_database.get("alice"), &[PasswordHash::sha1("new")]);
+        assert_eq!(htpasswd_database.expiry_of("alice"), None);
+        assert!(htpasswd_database.is_valid(&auth_data("alice", "new", &config), &config));
+        assert!(!htpasswd_database.is_valid(&auth_data("alice", "old", &config), &config));
+        assert!(htpasswd_database.is_valid(&auth_data("bob", "bob-secret", &config), &config));
+    }
+
+    #[test]
+    fn update_password_only_changes_existing_users() {
+        let mut htpasswd_database = HtpasswdDatabase::new();
+        htpasswd_database.add_with_expiry("alice", "old", SystemTime::now()).unwrap();
+
+        htpasswd_database.update_password("alice", PasswordHash::sha1("new")).unwrap();
+        match htpasswd_database.update_password("bob", PasswordHash::sha1("bob-secret")) {
+            Err(Error::UnknownUser { user }) => assert_eq!(user, "bob"),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let config = BasicAuthConfig::new();
+        assert_eq!(htpasswd_database.expiry_of("alice"), None);
+        assert!(htpasswd_database.is_valid(&auth_data("alice", "new", &config), &config));
+        assert!(!htpasswd_database.is_valid(&auth_data("alice", "old", &config), &config));
+        assert!(!htpasswd_database.contains("bob"));
+    }
+
+    struct PlaintextVerifier;
+    impl CustomVerifier for PlaintextVerifier {
+        fn prefix(&self) -> &str {
+            "$plain$"
+        }
+
+        fn verify(&self, stored: &str, candidate: &str) -> bool {
+            stored.strip_prefix(self.prefix()) == Some(candidate)
+        }
+    }
+
+    #[test]
+    fn weak_users_are_exactly_the_users_with_a_weak_hash() {
+        let options = LoadOptions::new()
+            .allow_multiple_hashes(true)
+            .custom_verifier(PlaintextVerifier);
+        let content = format!(
+            "sha1-user:{}\ncustom-user:$plain$secret\nmigrating-user:$plain$new\nmigrating-user:{}\n",
+            PasswordHash::sha1("secret"),
+            PasswordHash::sha1("old"),
+        );
+        let (entries, _) = htpasswd_entry::parse_entries_with(&content, "<test>", &options).unwrap();
+        let mut htpasswd_database = HtpasswdDatabase::from_entries(entries, &options).unwrap();
+        assert_eq!(htpasswd_database.weak_users(), vec!["migrating-user", "sha1-user"]);
+
+        #[cfg(feature = "md5_crypt")]
+        htpasswd_database.upsert(
+            "md5-crypt-user",
+            PasswordHash::parse("$1$saltsalt$qjXMvbEw8oaL.CzflDtaK/", &options).unwrap(),
+        );
+        #[cfg(feature = "scrypt")]
+        htpasswd_database.upsert("scrypt-user", crate::password_hash::tests::cheap_scrypt_hash("secret"));
+
+        // Dropping the old hash makes the migrated user strong
+        htpasswd_database.upsert("migrating-user", PasswordHash::parse("$plain$new", &options).unwrap());
+
+        let mut expected = vec!["sha1-user"];
+        if cfg!(feature = "md5_crypt") {
+            expected.insert(0, "md5-crypt-user");
+        }
+        assert_eq!(htpasswd_database.weak_users(), expected);
+        assert!(HtpasswdDatabase::new().weak_users().is_empty());
+    }
+
+    #[test]
+    fn generated_files_load_back_and_authenticate_every_user() {
+        let path = std::env::temp_dir()
+            .join(format!("actix_htpasswd-{}-generate", std::process::id()));
+        let users = [("alice", "alice-secret"), ("bob", "bob secret"), ("carol", "carol:secret")];
+
+        HtpasswdDatabase::generate(&users, HashScheme::Sha1, &path).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+
+        let htpasswd_database = HtpasswdDatabase::load(&path, &LoadOptions::new()).unwrap();
+        let config = BasicAuthConfig::new();
+        for (user, password) in &users {
+            assert!(htpasswd_database.is_valid(&auth_data(user, password, &config), &config), "{}", user);
+            assert!(!htpasswd_database.is_valid(&auth_data(user, "wrong", &config), &config), "{}", user);
+        }
+
+        // Saving again replaces the file, and leaves no temporary file behind
+        let mut htpasswd_database = htpasswd_database;
+        htpasswd_database.add("dave", "dave-secret").unwrap();
+        htpasswd_database.save(&path).unwrap();
+        assert_eq!(HtpasswdDatabase::load(&path, &LoadOptions::new()).unwrap(), htpasswd_database);
+        assert!(!temp_file_path(&path).exists());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "scrypt")]
+    #[test]
+    fn mixed_sha1_and_scrypt_files_are_loaded() {
+        // scrypt("bob-secret", "saltsalt", N = 16, r = 8, p = 1), from OpenSSL
+        let bob_hash = "$scrypt$ln=4,r=8,p=1$c2FsdHNhbHQ$dIn6llv93vLGEVx1ectdJvEtp4rhyz/hSoSGbR5DP/U";
+
+        let path = std::env::temp_dir()
+            .join(format!("actix_htpasswd-{}-mixed", std::process::id()));
+        fs::write(&path, format!("alice:{}\nbob:{}\n", PasswordHash::sha1("alice-secret"), bob_hash)).unwrap();
+        let htpasswd_database = HtpasswdDatabase::load(&path, &LoadOptions::new()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let config = BasicAuthConfig::new();
+        assert_eq!(htpasswd_database.schemes_of("alice"), vec![HashScheme::Sha1]);
+        assert_eq!(htpasswd_database.schemes_of("bob"), vec![HashScheme::Scrypt]);
+        assert!(htpasswd_database.is_valid(&auth_data("alice", "alice-secret", &config), &config));
+        assert!(htpasswd_database.is_valid(&auth_data("bob", "bob-secret", &config), &config));
+        assert!(!htpasswd_database.is_valid(&auth_data("bob", "alice-secret", &config), &config));
+        assert!(!htpasswd_database.is_valid(&auth_data("carol", "bob-secret", &config), &config));
+
+        // Unknown users are verified against a hash as costly as bob's
+        let dummy_hash = htpasswd_database.dummy_hash().unwrap();
+        assert!(dummy_hash.to_string().starts_with("$scrypt$ln=4,r=8,p=1$"));
+    }
+
+    #[cfg(feature = "scrypt")]
+    #[test]
+    fn dummy_hash_follows_the_changes_of_the_hashes() {
+        use crate::password_hash::tests::cheap_scrypt_hash;
+
+        let mut htpasswd_database = HtpasswdDatabase::new();
+        htpasswd_database.add("alice", "secret").unwrap();
+        assert_eq!(htpasswd_database.dummy_hash(), None);
+
+        htpasswd_database.upsert("bob", cheap_scrypt_hash("secret"));
+        assert!(htpasswd_database.dummy_hash().is_some());
+
+        let alice_only: HashSet<String> = vec!["alice".to_owned()].into_iter().collect();
+        assert_eq!(htpasswd_database.restrict_to(&alice_only).dummy_hash(), None);
+
+        let serialized = serde_json::to_string(&htpasswd_database).unwrap();
+        let deserialized: HtpasswdDatabase = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(deserialized, htpasswd_database);
+        assert!(deserialized.dummy_hash().is_some());
+
+        htpasswd_database.upsert("bob", PasswordHash::sha1("secret"));
+        assert_eq!(htpasswd_database.dummy_hash(), None);
+    }
+
+    #[test]
+    fn validate_reports_every_invalid_hash() {
+        let mut htpasswd_database = HtpasswdDatabase::new();
+        htpasswd_database.add("alice", "secret").unwrap();
+        assert!(htpasswd_database.validate().is_ok());
+
+        // Not loaded from a file, so never checked at parse time
+        htpasswd_database.upsert("bob", PasswordHash::Sha1(vec![0; 19]));
+        match htpasswd_database.validate().unwrap_err().as_slice() {
+            [Error::InvalidDigestLength { user, expected, got }] => {
+                assert_eq!(user, "bob");
+                assert_eq!((*expected, *got), (20, 19));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        htpasswd_database.upsert("carol", PasswordHash::Sha1(Vec::new()));
+        assert_eq!(htpasswd_database.validate().unwrap_err().len(), 2);
+    }
+
+    fn team_databases() -> (HtpasswdDatabase, HtpasswdDatabase) {
+        let mut first = HtpasswdDatabase::new();
+        first.add("alice", "first-alice").unwrap();
+        first.add("bob", "first-bob").unwrap();
+        let mut last = HtpasswdDatabase::new();
+        last.add_with_expiry("alice", "last-alice", SystemTime::now()).unwrap();
+        last.add("carol", "last-carol").unwrap();
+        (first, last)
+    }
+
+    #[test]
+    fn merge_with_first_wins_keeps_the_existing_users() {
+        let (mut merged, last) = team_databases();
+        merged.merge(last, ConflictStrategy::FirstWins).unwrap();
+
+        assert_eq!(merged.get("alice"), &[PasswordHash::sha1("first-alice")]);
+        assert_eq!(merged.expiry_of("alice"), None);
+        assert_eq!(merged.get("bob"), &[PasswordHash::sha1("first-bob")]);
+        assert_eq!(merged.get("carol"), &[PasswordHash::sha1("last-carol")]);
+    }
+
+    #[test]
+    fn merge_with_last_wins_replaces_the_existing_users() {
+        let (mut merged, last) = team_databases();
+        merged.merge(last, ConflictStrategy::LastWins).unwrap();
+
+        // Along with the expiry of the password
+        assert_eq!(merged.get("alice"), &[PasswordHash::sha1("last-alice")]);
+        assert!(merged.expiry_of("alice").is_some());
+        assert_eq!(merged.get("bob"), &[PasswordHash::sha1("first-bob")]);
+        assert_eq!(merged.get("carol"), &[PasswordHash::sha1("last-carol")]);
+    }
+
+    #[test]
+    fn merge_with_fail_leaves_the_database_untouched() {
+        let (mut merged, last) = team_databases();
+        let before = merged.clone();
+        match merged.merge(last, ConflictStrategy::Fail) {
+            Err(Error::DuplicateUser { user }) => assert_eq!(user, "alice"),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(merged, before);
+    }
+
+    fn load_and_validate_content(name: &str, content: &str, options: &LoadOptions) -> Result<HtpasswdDatabase, Vec<Error>> {
+        let path = std::env::temp_dir()
+            .join(format!("actix_htpasswd-{}-{}", std::process::id(), name));
+        fs::write(&path, content).unwrap();
+        let result = HtpasswdDatabase::load_and_validate(&path, options);
+        fs::remove_file(&path).unwrap();
+        result
+    }
+
+    #[test]
+    fn load_and_validate_accepts_a_usable_file() {
+        let content = format!("alice:{}\n", PasswordHash::sha1("secret"));
+        let htpasswd_database = load_and_validate_content("usable", &content, &LoadOptions::new()).unwrap();
+        assert!(htpasswd_database.contains("alice"));
+    }
+
+    #[test]
+    fn load_and_validate_rejects_an_empty_file() {
+        match load_and_validate_content("empty", "\n  \n", &LoadOptions::new()).unwrap_err().as_slice() {
+            [Error::EmptyHtpasswdDatabase { path_string }] => assert!(path_string.ends_with("-empty")),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn load_and_validate_rejects_disallowed_schemes() {
+        let content = format!("alice:{}\n", PasswordHash::sha1("secret"));
+        let options = LoadOptions::new().disallow_scheme(HashScheme::Sha1);
+        match load_and_validate_content("disallowed", &content, &options).unwrap_err().as_slice() {
+            [Error::DisallowedHashScheme { user, scheme }] => {
+                assert_eq!(user, "alice");
+                assert_eq!(scheme, "SHA-1");
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        // Other schemes are not concerned
+        let options = LoadOptions::new().disallow_scheme(HashScheme::Md5Crypt);
+        assert!(load_and_validate_content("allowed", &content, &options).is_ok());
+    }
+
+    #[test]
+    fn load_and_validate_rejects_invalid_hashes() {
+        // 19 bytes instead of 20
+        let content = format!("alice:{}\nbob:{{SHA}}{}\n", PasswordHash::sha1("secret"), base64::encode(&[0; 19]));
+        match load_and_validate_content("invalid", &content, &LoadOptions::new()).unwrap_err().as_slice() {
+            [Error::InvalidHashLength { line, expected, got, .. }] => {
+                assert_eq!((*line, *expected, *got), (2, 20, 19));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn generate_rejects_duplicate_users() {
+        let path = std::env::temp_dir()
+            .join(format!("actix_htpasswd-{}-generate-duplicate", std::process::id()));
+        match HtpasswdDatabase::generate(&[("alice", "a"), ("alice", "b")], HashScheme::Sha1, &path) {
+            Err(Error::DuplicateUser { user }) => assert_eq!(user, "alice"),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,305 @@
+use std::convert::TryFrom;
+
+use serde::{
+    Serialize,
+    Deserialize
+};
+
+use crate::{
+    error::Error,
+    load_options::LoadOptions,
+    password_hash::{
+        ParseFailure,
+        PasswordHash
+    },
+};
+
+// Name used in errors for credentials which don't come from a file
+const IN_MEMORY_PATH_STRING: &str = "<in-memory content>";
+
+// One parsed line of an Htpasswd file. Unlike the HtpasswdDatabase, a list of
+// entries keeps the order of the file, the line numbers (starting at 1), and
+// possible duplicate users.
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct HtpasswdEntry {
+    pub user: String,
+    pub hash: PasswordHash,
+    pub line: Option<usize>,
+}
+
+// User credentials as written in an app configuration file (TOML, JSON...),
+// e.g. `{ name = "alice", hash = "{SHA}..." }`, to embed the users in the main
+// config instead of a separate Htpasswd file. The hash uses the same format
+// as in Htpasswd files: an invalid or unsupported hash fails the
+// deserialization, with an error naming the user but not the hash. Custom
+// verifiers cannot be used there.
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(try_from = "RawUserEntry")]
+pub struct UserEntry {
+    pub name: String,
+    pub hash: PasswordHash,
+}
+
+// The hash is only parsed once the name is known
+#[derive(Deserialize)]
+struct RawUserEntry {
+    name: String,
+    hash: String,
+}
+
+impl TryFrom<RawUserEntry> for UserEntry {
+    type Error = Error;
+
+    fn try_from(raw_user_entry: RawUserEntry) -> Result<Self, Self::Error> {
+        let hash = PasswordHash::parse(&raw_user_entry.hash, &LoadOptions::default())
+            .map_err(|parse_failure| parse_failure.for_user(&raw_user_entry.name))?;
+        Ok(UserEntry {
+            name: raw_user_entry.name,
+            hash,
+        })
+    }
+}
+
+// Parse the content of an Htpasswd file into its entries, in order
+pub fn parse_entries(content: &str) -> Result<Vec<HtpasswdEntry>, Error> {
+    parse_entries_with(content, IN_MEMORY_PATH_STRING, &LoadOptions::default())
+        .map(|(entries, _)| entries)
+}
+
+// Also returns the errors of the lines skipped with `skip_invalid_lines`
+pub(crate) fn parse_entries_with(
+    content: &str,
+    path_string: &str,
+    options: &LoadOptions
+) -> Result<(Vec<HtpasswdEntry>, Vec<Error>), Error> {
+    let mut entries = Vec::new();
+    let mut skipped = Vec::new();
+
+    // `lines()` strips the line terminator, be it "\n" or "\r\n". The line
+    // itself is kept as-is: spaces might be part of the username or of the
+    // hash.
+    for (i, line) in content.lines().enumerate() {
+        let line_number = i + 1;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        // Cut the htpasswd line on the first ':', which splits the username
+        // from the password hash. The hash scheme is then recognized from the
+        // prefix of the hash.
+        let mut parts = line.splitn(2, ':');
+        let parsed = match (parts.next(), parts.next()) {
+            (Some(user), Some(hash_string)) => parse_hash(hash_string, path_string, line_number, options)
+                .map(|hash| (user, hash)),
+            _ => Err(Error::MalformedHtpasswdLine {
+                path_string: path_string.to_owned(),
+                line: line_number,
+            }),
+        };
+
+        let (user, hash) = match parsed {
+            Ok(parsed) => parsed,
+            Err(error) if options.skip_invalid_lines => {
+                log::warn!("Skipping invalid Htpasswd line: {}", error);
+                skipped.push(error);
+                continue;
+            }
+            Err(error) => return Err(error),
+        };
+
+        entries.push(HtpasswdEntry {
+            user: user.to_owned(),
+            hash,
+            line: Some(line_number),
+        });
+    }
+
+    Ok((entries, skipped))
+}
+
+fn parse_hash(
+    hash_string: &str,
+    path_string: &str,
+    line_number: usize,
+    options: &LoadOptions
+) -> Result<PasswordHash, Error> {
+    PasswordHash::parse(hash_string, options)
+        .map_err(|parse_failure| match parse_failure {
+            ParseFailure::UnknownScheme => Error::MalformedHtpasswdLine {
+                path_string: path_string.to_owned(),
+                line: line_number,
+            },
+            ParseFailure::UnsupportedScheme(scheme) => Error::UnsupportedHashScheme {
+                path_string: path_string.to_owned(),
+                scheme: scheme.to_owned(),
+                line: line_number,
+            },
+            ParseFailure::InvalidEncoding => Error::InvalidPasswordString {
+                path_string: path_string.to_owned(),
+                line: line_number,
+            },
+            ParseFailure::InvalidLength { expected, got } => Error::InvalidHashLength {
+                path_string: path_string.to_owned(),
+                line: line_number,
+                expected,
+                got,
+            },
+            #[cfg(feature = "scrypt")]
+            parse_failure @ ParseFailure::CostTooHigh { .. } => Error::ExcessiveHashCost {
+                path_string: path_string.to_owned(),
+                line: line_number,
+                reason: parse_failure.to_string(),
+            },
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crlf_line_endings_are_stripped() {
+        let alice = PasswordHash::sha1("alice-secret");
+        let bob = PasswordHash::sha1("bob-secret");
+        let content = format!("alice:{}\r\nbob:{}\r\n", alice, bob);
+
+        let entries = parse_entries(&content).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].user, "alice");
+        assert_eq!(entries[0].hash, alice);
+        assert_eq!(entries[1].user, "bob");
+        assert_eq!(entries[1].hash, bob);
+    }
+
+    #[test]
+    fn spaces_are_kept_in_usernames() {
+        let hash = PasswordHash::sha1("secret");
+        let entries = parse_entries(&format!("alice :{}\r\n", hash)).unwrap();
+        assert_eq!(entries[0].user, "alice ");
+        assert_eq!(entries[0].hash, hash);
+    }
+
+    #[test]
+    fn entries_keep_the_file_order_and_line_numbers() {
+        let content = format!(
+            "carol:{}\n\nalice:{}\n   \nbob:{}\n",
+            PasswordHash::sha1("c"), PasswordHash::sha1("a"), PasswordHash::sha1("b")
+        );
+
+        let entries = parse_entries(&content).unwrap();
+        let users_and_lines: Vec<(&str, Option<usize>)> = entries.iter()
+            .map(|entry| (entry.user.as_str(), entry.line))
+            .collect();
+        assert_eq!(users_and_lines, vec![("carol", Some(1)), ("alice", Some(3)), ("bob", Some(5))]);
+    }
+
+    #[test]
+    fn load_errors_report_lines_starting_at_1() {
+        let content = format!("alice:{}\nnot a valid line\n", PasswordHash::sha1("a"));
+        match parse_entries(&content) {
+            Err(Error::MalformedHtpasswdLine { line, .. }) => assert_eq!(line, 2),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    fn deserialize_user_entry(name: &str, hash: &str) -> Result<UserEntry, serde::de::value::Error> {
+        let fields = vec![("name", name), ("hash", hash)];
+        UserEntry::deserialize(serde::de::value::MapDeserializer::new(fields.into_iter()))
+    }
+
+    #[test]
+    fn user_entries_are_deserialized_with_their_hash() {
+        let hash = PasswordHash::sha1("secret");
+        let user_entry = deserialize_user_entry("alice", &hash.to_string()).unwrap();
+        assert_eq!(user_entry.name, "alice");
+        assert_eq!(user_entry.hash, hash);
+    }
+
+    #[test]
+    fn user_entry_errors_name_the_user_but_not_the_hash() {
+        let short_hash = format!("{{SHA}}{}", base64::encode(&[0; 19]));
+        for hash_string in &["garbage-hash", "$2y$05$bcrypthashbcrypthash", "{SHA}@@@@", short_hash.as_str()] {
+            let message = deserialize_user_entry("alice", hash_string).unwrap_err().to_string();
+            assert!(message.contains("\"alice\""), "{}", message);
+            assert!(!message.contains(hash_string), "{}", message);
+        }
+    }
+
+    #[test]
+    fn user_entry_errors_follow_the_parse_failure() {
+        let raw_user_entry = |hash: &str| RawUserEntry { name: "alice".to_owned(), hash: hash.to_owned() };
+
+        match UserEntry::try_from(raw_user_entry("$argon2id$v=19$m=16,t=2,p=1$c2FsdA$aGFzaA")) {
+            Err(Error::InvalidUserHash { user, reason }) => {
+                assert_eq!(user, "alice");
+                assert!(reason.contains("argon2"), "{}", reason);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        match UserEntry::try_from(raw_user_entry(&format!("{{SHA}}{}", base64::encode(&[0; 19])))) {
+            Err(Error::InvalidDigestLength { user, expected: 20, got: 19 }) => assert_eq!(user, "alice"),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn well_known_schemes_are_reported_as_unsupported() {
+        match parse_entries("alice:$argon2id$v=19$m=16,t=2,p=1$c2FsdA$aGFzaA\n") {
+            Err(Error::UnsupportedHashScheme { scheme, line, .. }) => {
+                assert_eq!(scheme, "argon2");
+                assert_eq!(line, 1);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn garbage_hashes_are_malformed_lines() {
+        match parse_entries("alice:not-a-hash\n") {
+            Err(Error::MalformedHtpasswdLine { line, .. }) => assert_eq!(line, 1),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn invalid_lines_are_only_skipped_when_asked() {
+        let content = format!(
+            "alice:{}\nnot a valid line\nbob:$2y$05$bcrypthash\ncarol:{}\n",
+            PasswordHash::sha1("a"), PasswordHash::sha1("c")
+        );
+
+        let strict = LoadOptions::new();
+        match parse_entries_with(&content, "test", &strict) {
+            Err(Error::MalformedHtpasswdLine { line, .. }) => assert_eq!(line, 2),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let lenient = LoadOptions::new().skip_invalid_lines(true);
+        let (entries, skipped) = parse_entries_with(&content, "test", &lenient).unwrap();
+        let users: Vec<&str> = entries.iter().map(|entry| entry.user.as_str()).collect();
+        assert_eq!(users, vec!["alice", "carol"]);
+        assert_eq!(entries[1].line, Some(4));
+        assert_eq!(skipped.len(), 2);
+        match &skipped[1] {
+            Error::UnsupportedHashScheme { line, .. } => assert_eq!(*line, 3),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn sha_digests_of_the_wrong_length_are_reported() {
+        let content = format!("alice:{{SHA}}{}\n", base64::encode(&[0; 19]));
+        match parse_entries(&content) {
+            Err(Error::InvalidHashLength { line, expected, got, .. }) => {
+                assert_eq!((line, expected, got), (1, 20, 19));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        match parse_entries("alice:{SHA}@@@@\n") {
+            Err(Error::InvalidPasswordString { line, .. }) => assert_eq!(line, 1),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use std::marker::PhantomData;
+
+use actix_web::{
+    dev::RequestHead,
+    guard::Guard,
+    web::Data,
+};
+
+use crate::{
+    auth_control::AuthResult,
+    auth_data::CredentialStatus,
+    basic_auth_config::BasicAuthConfig,
+    htpasswd_database::HtpasswdDatabase,
+    user_control_policy::UserControlPolicy,
+};
+
+// Route guard matching only the requests allowed by the `U` policy, so that
+// other requests can fall through to another handler (e.g. a public version
+// of the resource).
+//
+// Guards run before extraction and only get to see the request head, not
+// the app data. Hence the guard must be given its own handle on the
+// database (and its own config) at construction time, instead of picking
+// them from the app like `AuthControl` does. Also, a guard can only match or
+// not match: no 401 or 403 response is produced, and the authentication
+// result is not handed to the handler (use `AuthControl` for that). Finally,
+// guards are synchronous, so expensive hashes (scrypt) are verified inline
+// rather than on the blocking thread pool.
+//
+// The request head doesn't tell whether the server itself is bound with TLS,
+// so `BasicAuthConfig::require_secure_transport` is not honored by the
+// guard: the credentials of a plain HTTP request are checked all the same.
+// Where a secure transport is required, put an `AuthControl` extractor on
+// the guarded handlers as well, which refuses them before any check.
+pub struct HtpasswdGuard<U: UserControlPolicy> {
+    _phantom_data: PhantomData<U>, // keep UserControlPolicy type
+    htpasswd_database: Data<HtpasswdDatabase>,
+    config: BasicAuthConfig,
+}
+impl<U: UserControlPolicy> HtpasswdGuard<U> {
+    pub fn new(htpasswd_database: Data<HtpasswdDatabase>) -> HtpasswdGuard<U> {
+        HtpasswdGuard {
+            _phantom_data: PhantomData,
+            htpasswd_database,
+            config: BasicAuthConfig::default(),
+        }
+    }
+
+    pub fn config(mut self, config: BasicAuthConfig) -> HtpasswdGuard<U> {
+        self.config = config;
+        self
+    }
+}
+
+impl<U: UserControlPolicy> Guard for HtpasswdGuard<U> {
+    fn check(&self, request: &RequestHead) -> bool {
+        // Malformed headers and invalid credentials never match
+        let auth_result = match CredentialStatus::from_headers(&request.headers, &self.config) {
+            CredentialStatus::Present(auth_data) => {
+                if self.htpasswd_database.is_valid(&auth_data, &self.config) {
+                    AuthResult::LoggedUser {
+                        user: auth_data.user,
+                    }
+                } else {
+                    return false;
+                }
+            },
+            CredentialStatus::Absent => AuthResult::Anonymous,
+            CredentialStatus::Malformed(_) => return false,
+        };
+
+        U::allows_head(&auth_result, request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        http::header::AUTHORIZATION,
+        test::TestRequest,
+    };
+
+    use super::*;
+    use crate::{
+        auth_data::basic_auth_header,
+        user_control_policy::ReadAnyoneWriteLoggedIn,
+    };
+
+    #[test]
+    fn guard_uses_the_head_based_policy() {
+        let mut htpasswd_database = HtpasswdDatabase::new();
+        htpasswd_database.add("alice", "secret").unwrap();
+        let guard = HtpasswdGuard::<ReadAnyoneWriteLoggedIn>::new(Data::new(htpasswd_database));
+
+        assert!(guard.check(TestRequest::get().to_http_request().head()));
+        assert!(!guard.check(TestRequest::post().to_http_request().head()));
+
+        let logged_post = TestRequest::post()
+            .header(AUTHORIZATION, basic_auth_header("alice", "secret"))
+            .to_http_request();
+        assert!(guard.check(logged_post.head()));
+        let wrong_password_get = TestRequest::get()
+            .header(AUTHORIZATION, basic_auth_header("alice", "wrong"))
+            .to_http_request();
+        assert!(!guard.check(wrong_password_get.head()));
+    }
+}
@@ -0,0 +1,143 @@
+use std::{
+    collections::{
+        BTreeMap,
+        HashMap,
+    },
+    net::IpAddr,
+    sync::Mutex,
+    time::Instant,
+};
+
+// Enough for a busy service, while keeping the memory use to a few MB
+const DEFAULT_MAX_TRACKED_IPS: usize = 10_000;
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+    // Position in the recency order, see `Buckets::recency`
+    last_use: u64,
+}
+
+#[derive(Debug, Default)]
+struct Buckets {
+    // `None` is the bucket shared by the clients of unknown IP
+    by_ip: HashMap<Option<IpAddr>, Bucket>,
+    // IPs by last use, oldest first, to evict the least recently used one
+    recency: BTreeMap<u64, Option<IpAddr>>,
+    next_use: u64,
+}
+
+// Caps the rate of requests per client IP on the routes using
+// `AuthControl`, whether their credentials are valid or not, to curb abuse.
+// Opt-in: add a `Data<IpRateLimiter>` to the actix app. Requests over the
+// cap are rejected with a 429 (`FailureKind::Throttled`) before any
+// credential is checked.
+//
+// Each IP gets a token bucket holding up to `capacity` requests, refilled
+// continuously at `refill_per_second`. The client IP is the peer address,
+// or, for requests coming from one of the `trusted_proxies` of the
+// BasicAuthConfig, the last hop of its `forwarded_for_header` which is not a
+// trusted proxy. The proxies must append the address of their peer to the
+// header sent by the client in a single line (as nginx does with
+// `$proxy_add_x_forwarded_for`), not in a separate header line, as the order
+// of repeated lines is lost. All the requests whose client IP is unknown
+// (no such hop, or an unparsable one) share a single bucket, so that a
+// client cannot dodge the limit by making up its forwarded header.
+//
+// At most `max_tracked_ips` buckets are kept (10000 by default): past that,
+// the least recently used one is forgotten, which at worst grants a fresh
+// bucket to a returning IP.
+#[derive(Debug)]
+pub struct IpRateLimiter {
+    capacity: f64,
+    refill_per_second: f64,
+    max_tracked_ips: usize,
+    buckets: Mutex<Buckets>,
+}
+impl IpRateLimiter {
+    pub fn new(capacity: u32, refill_per_second: f64) -> IpRateLimiter {
+        IpRateLimiter {
+            capacity: f64::from(capacity),
+            refill_per_second,
+            max_tracked_ips: DEFAULT_MAX_TRACKED_IPS,
+            buckets: Mutex::new(Buckets::default()),
+        }
+    }
+
+    pub fn max_tracked_ips(mut self, max_tracked_ips: usize) -> IpRateLimiter {
+        self.max_tracked_ips = max_tracked_ips.max(1);
+        self
+    }
+
+    // Take a token from the bucket of the IP (the shared one if unknown), and
+    // tell whether there was one
+    pub(crate) fn try_acquire(&self, ip: Option<IpAddr>) -> bool {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        let Buckets { by_ip, recency, next_use } = &mut *buckets;
+
+        let last_use = *next_use;
+        *next_use += 1;
+
+        if !by_ip.contains_key(&ip) && by_ip.len() >= self.max_tracked_ips {
+            if let Some((_, evicted_ip)) = recency.pop_first() {
+                by_ip.remove(&evicted_ip);
+            }
+        }
+
+        let bucket = by_ip.entry(ip).or_insert(Bucket {
+            tokens: self.capacity,
+            last_refill: now,
+            last_use,
+        });
+        recency.remove(&bucket.last_use);
+        recency.insert(last_use, ip);
+        bucket.last_use = last_use;
+
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.refill_per_second).min(self.capacity);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(ip: &str) -> Option<IpAddr> {
+        Some(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn least_recently_used_bucket_is_evicted_past_the_cap() {
+        let ip_rate_limiter = IpRateLimiter::new(1, 0.0).max_tracked_ips(2);
+        assert!(ip_rate_limiter.try_acquire(ip("198.51.100.1")));
+        assert!(ip_rate_limiter.try_acquire(ip("198.51.100.2")));
+        // Using it again makes the first IP the most recent one
+        assert!(!ip_rate_limiter.try_acquire(ip("198.51.100.1")));
+        assert!(ip_rate_limiter.try_acquire(ip("198.51.100.3")));
+
+        // The second IP was forgotten, and gets a fresh bucket
+        assert!(!ip_rate_limiter.try_acquire(ip("198.51.100.1")));
+        assert!(ip_rate_limiter.try_acquire(ip("198.51.100.2")));
+        assert_eq!(ip_rate_limiter.buckets.lock().unwrap().by_ip.len(), 2);
+        assert_eq!(ip_rate_limiter.buckets.lock().unwrap().recency.len(), 2);
+    }
+
+    #[test]
+    fn unknown_ips_share_a_bucket() {
+        let ip_rate_limiter = IpRateLimiter::new(2, 0.0);
+        assert!(ip_rate_limiter.try_acquire(None));
+        assert!(ip_rate_limiter.try_acquire(None));
+        assert!(!ip_rate_limiter.try_acquire(None));
+        assert!(ip_rate_limiter.try_acquire(ip("198.51.100.1")));
+    }
+}
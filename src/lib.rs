@@ -1,14 +1,71 @@
 pub mod auth_control;
+pub mod auth_data;
+pub mod auth_error;
+pub mod auth_stats;
+pub mod basic_auth_config;
+pub mod basic_credentials;
+pub mod challenge;
+pub mod custom_verifier;
 pub mod error;
+pub mod hash_lookup;
 pub mod htpasswd_database;
+pub mod htpasswd_entry;
+pub mod htpasswd_guard;
+pub mod ip_rate_limiter;
+pub mod load_options;
+pub mod login_tracker;
+#[cfg(feature = "md5_crypt")]
+mod md5_crypt;
+pub mod merged_reloadable_database;
+pub mod password_hash;
+pub mod response_map;
 pub mod user_control_policy;
-
-mod auth_data;
+pub mod verification_limiter;
 
 pub use auth_control::{
     AuthControl,
+    AuthControlIn,
     AuthResult
 };
+pub use auth_data::{
+    basic_auth_header,
+    AuthData,
+    CredentialStatus,
+    SubmittedPassword
+};
+pub use auth_error::AuthError;
+pub use auth_stats::{
+    AuthStats,
+    AuthStatsSnapshot
+};
+pub use basic_auth_config::BasicAuthConfig;
+pub use basic_credentials::BasicCredentials;
+pub use challenge::force_reauthentication;
+pub use custom_verifier::CustomVerifier;
 pub use error::Error;
+pub use hash_lookup::HashLookup;
 pub use htpasswd_database::HtpasswdDatabase;
+pub use htpasswd_entry::{
+    parse_entries,
+    HtpasswdEntry,
+    UserEntry
+};
+pub use htpasswd_guard::HtpasswdGuard;
+pub use ip_rate_limiter::IpRateLimiter;
+pub use load_options::LoadOptions;
+pub use login_tracker::LoginTracker;
+pub use merged_reloadable_database::{
+    ConflictStrategy,
+    MergedReloadableDatabase
+};
+pub use password_hash::{
+    hash_password,
+    HashScheme,
+    PasswordHash
+};
+pub use response_map::{
+    FailureKind,
+    ResponseMap
+};
 pub use user_control_policy::UserControlPolicy;
+pub use verification_limiter::VerificationLimiter;
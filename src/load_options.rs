@@ -0,0 +1,70 @@
+use std::{
+    fmt::{
+        Debug,
+        Formatter,
+    },
+    sync::Arc,
+};
+
+use crate::{
+    custom_verifier::CustomVerifier,
+    password_hash::HashScheme,
+};
+
+#[derive(Clone, Default)]
+pub struct LoadOptions {
+    pub(crate) custom_verifiers: Vec<Arc<dyn CustomVerifier>>,
+    pub(crate) allow_multiple_hashes: bool,
+    pub(crate) skip_invalid_lines: bool,
+    pub(crate) disallowed_schemes: Vec<HashScheme>,
+}
+impl LoadOptions {
+    pub fn new() -> LoadOptions {
+        LoadOptions::default()
+    }
+
+    pub fn custom_verifier<V: CustomVerifier + 'static>(mut self, verifier: V) -> LoadOptions {
+        self.custom_verifiers.push(Arc::new(verifier));
+        self
+    }
+
+    // Accept several lines for the same user instead of failing with
+    // `DuplicateUser`, typically while a file is being migrated from a scheme
+    // to another (e.g. an old SHA-1 hash and a new scrypt hash of the same
+    // password). The password is checked against every hash of the user, and
+    // the login succeeds if any of them matches. Once the migration is done,
+    // remove the old lines so that the weak hashes stop being accepted.
+    pub fn allow_multiple_hashes(mut self, allow_multiple_hashes: bool) -> LoadOptions {
+        self.allow_multiple_hashes = allow_multiple_hashes;
+        self
+    }
+
+    // Best-effort loading: skip (and log) the lines which cannot be parsed,
+    // instead of failing on the first one. Use `load_with_warnings` to get
+    // the errors of the skipped lines. Duplicate users still fail the load.
+    pub fn skip_invalid_lines(mut self, skip_invalid_lines: bool) -> LoadOptions {
+        self.skip_invalid_lines = skip_invalid_lines;
+        self
+    }
+
+    // Fail the load with `DisallowedHashScheme` if any user has a hash of the
+    // given scheme, e.g. to refuse SHA-1 hashes once every user has migrated
+    pub fn disallow_scheme(mut self, scheme: HashScheme) -> LoadOptions {
+        self.disallowed_schemes.push(scheme);
+        self
+    }
+}
+
+impl Debug for LoadOptions {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        f.debug_struct("LoadOptions")
+            .field(
+                "custom_verifiers",
+                &self.custom_verifiers.iter().map(|v| v.prefix()).collect::<Vec<_>>()
+            )
+            .field("allow_multiple_hashes", &self.allow_multiple_hashes)
+            .field("skip_invalid_lines", &self.skip_invalid_lines)
+            .field("disallowed_schemes", &self.disallowed_schemes)
+            .finish()
+    }
+}
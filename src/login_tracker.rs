@@ -0,0 +1,144 @@
+use std::{
+    collections::{
+        BTreeMap,
+        HashMap,
+    },
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use crate::htpasswd_database::HtpasswdDatabase;
+
+// Same order of magnitude as the IpRateLimiter
+const DEFAULT_MAX_TRACKED_USERS: usize = 10_000;
+
+#[derive(Debug)]
+struct Login {
+    at: SystemTime,
+    // Position in the recency order, see `Logins::recency`
+    order: u64,
+}
+
+#[derive(Debug, Default)]
+struct Logins {
+    by_user: HashMap<String, Login>,
+    // Users by last login, oldest first, to evict the least recent one
+    recency: BTreeMap<u64, String>,
+    next_order: u64,
+}
+
+// Records when each user last authenticated successfully, e.g. to build a
+// "last seen" report. Opt-in: add a `Data<LoginTracker>` to the actix app
+// and `AuthControl` updates it on every successful authentication.
+//
+// The tracker is process-local and purely in memory: it is not shared
+// between workers of different processes, and it starts empty again on
+// restart. It holds one entry per user who logged in, including the users
+// forwarded by a trusted proxy (see `BasicAuthConfig::proxy_user_header`),
+// which are not in any database. At most `max_tracked_users` entries are
+// kept (10000 by default): past that, the user whose last login is the
+// oldest is forgotten. Call `prune` after reloading the database to forget
+// about removed users.
+#[derive(Debug)]
+pub struct LoginTracker {
+    max_tracked_users: usize,
+    logins: Mutex<Logins>,
+}
+impl LoginTracker {
+    pub fn new() -> LoginTracker {
+        LoginTracker::default()
+    }
+
+    pub fn max_tracked_users(mut self, max_tracked_users: usize) -> LoginTracker {
+        self.max_tracked_users = max_tracked_users.max(1);
+        self
+    }
+
+    pub(crate) fn record(&self, user: &str) {
+        let mut logins = self.logins.lock().unwrap();
+        let Logins { by_user, recency, next_order } = &mut *logins;
+
+        let order = *next_order;
+        *next_order += 1;
+
+        if !by_user.contains_key(user) && by_user.len() >= self.max_tracked_users {
+            if let Some((_, evicted_user)) = recency.pop_first() {
+                by_user.remove(&evicted_user);
+            }
+        }
+
+        let login = Login {
+            at: SystemTime::now(),
+            order,
+        };
+        if let Some(previous_login) = by_user.insert(user.to_owned(), login) {
+            recency.remove(&previous_login.order);
+        }
+        recency.insert(order, user.to_owned());
+    }
+
+    pub fn last_login(&self, user: &str) -> Option<SystemTime> {
+        self.logins.lock().unwrap().by_user.get(user).map(|login| login.at)
+    }
+
+    // Snapshot of the last successful login of every tracked user
+    pub fn last_logins(&self) -> HashMap<String, SystemTime> {
+        self.logins.lock().unwrap().by_user.iter()
+            .map(|(user, login)| (user.clone(), login.at))
+            .collect()
+    }
+
+    // Forget about the users which are no longer in the database
+    pub fn prune(&self, htpasswd_database: &HtpasswdDatabase) {
+        let mut logins = self.logins.lock().unwrap();
+        let Logins { by_user, recency, .. } = &mut *logins;
+        by_user.retain(|user, _| htpasswd_database.contains(user));
+        recency.retain(|_, user| by_user.contains_key(user));
+    }
+}
+
+impl Default for LoginTracker {
+    fn default() -> LoginTracker {
+        LoginTracker {
+            max_tracked_users: DEFAULT_MAX_TRACKED_USERS,
+            logins: Mutex::new(Logins::default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn least_recent_login_is_evicted_past_the_cap() {
+        let login_tracker = LoginTracker::new().max_tracked_users(2);
+        login_tracker.record("alice");
+        login_tracker.record("bob");
+        // Logging in again makes alice the most recent one
+        login_tracker.record("alice");
+        login_tracker.record("carol");
+
+        assert!(login_tracker.last_login("alice").is_some());
+        assert!(login_tracker.last_login("bob").is_none());
+        assert!(login_tracker.last_login("carol").is_some());
+        assert_eq!(login_tracker.last_logins().len(), 2);
+    }
+
+    #[test]
+    fn prune_forgets_users_out_of_the_database() {
+        let mut htpasswd_database = HtpasswdDatabase::new();
+        htpasswd_database.add("alice", "secret").unwrap();
+
+        let login_tracker = LoginTracker::new().max_tracked_users(2);
+        login_tracker.record("alice");
+        login_tracker.record("proxied-user");
+        login_tracker.prune(&htpasswd_database);
+        assert_eq!(login_tracker.last_logins().keys().collect::<Vec<_>>(), vec!["alice"]);
+
+        // The pruned entry no longer counts towards the cap
+        login_tracker.record("bob");
+        assert!(login_tracker.last_login("alice").is_some());
+        assert!(login_tracker.last_login("bob").is_some());
+    }
+}
@@ -0,0 +1,109 @@
+use md5::{
+    Digest,
+    Md5
+};
+
+pub(crate) const MAGIC: &str = "$1$";
+
+// Alphabet of the crypt flavor of base64
+const CRYPT_BASE64: &[u8; 64] = b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+const MAX_SALT_LENGTH: usize = 8;
+const ENCODED_HASH_LENGTH: usize = 22;
+
+// Split a "$1$<salt>$<hash>" string, checking its shape
+pub(crate) fn split(crypt_string: &str) -> Option<(&str, &str)> {
+    let mut parts = crypt_string.strip_prefix(MAGIC)?.splitn(2, '$');
+    let (salt, encoded_hash) = (parts.next()?, parts.next()?);
+    let is_well_formed = salt.len() <= MAX_SALT_LENGTH
+        && encoded_hash.len() == ENCODED_HASH_LENGTH
+        && encoded_hash.bytes().all(|byte| CRYPT_BASE64.contains(&byte));
+    if is_well_formed {
+        Some((salt, encoded_hash))
+    } else {
+        None
+    }
+}
+
+// The MD5-based crypt() of FreeBSD, as found in old /etc/shadow files.
+// Returns the encoded hash, without the magic and the salt.
+pub(crate) fn encoded_hash(password: &[u8], salt: &[u8]) -> String {
+    let mut alternate = Md5::new();
+    alternate.input(password);
+    alternate.input(salt);
+    alternate.input(password);
+    let alternate = alternate.result();
+
+    let mut context = Md5::new();
+    context.input(password);
+    context.input(MAGIC);
+    context.input(salt);
+    for chunk in password.chunks(16) {
+        context.input(&alternate[..chunk.len()]);
+    }
+    let mut length = password.len();
+    while length > 0 {
+        if length & 1 == 1 {
+            context.input([0]);
+        } else {
+            context.input(&password[..1]);
+        }
+        length >>= 1;
+    }
+    let mut digest = context.result();
+
+    // Deliberately slow down the computation (by 1990 standards)
+    for round in 0..1000 {
+        let mut context = Md5::new();
+        if round & 1 == 1 {
+            context.input(password);
+        } else {
+            context.input(digest);
+        }
+        if round % 3 != 0 {
+            context.input(salt);
+        }
+        if round % 7 != 0 {
+            context.input(password);
+        }
+        if round & 1 == 1 {
+            context.input(digest);
+        } else {
+            context.input(password);
+        }
+        digest = context.result();
+    }
+
+    let mut encoded = String::with_capacity(ENCODED_HASH_LENGTH);
+    let mut push = |value: u32, count: usize| {
+        for i in 0..count {
+            encoded.push(CRYPT_BASE64[((value >> (6 * i)) & 0x3f) as usize] as char);
+        }
+    };
+    for &(a, b, c) in &[(0, 6, 12), (1, 7, 13), (2, 8, 14), (3, 9, 15), (4, 10, 5)] {
+        push((u32::from(digest[a]) << 16) | (u32::from(digest[b]) << 8) | u32::from(digest[c]), 4);
+    }
+    push(u32::from(digest[11]), 2);
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_vectors() {
+        assert_eq!(encoded_hash(b"password", b"saltsalt"), "qjXMvbEw8oaL.CzflDtaK/");
+        // Longer than an MD5 digest (16 bytes)
+        assert_eq!(encoded_hash(b"correct horse battery staple", b"saltsalt"), "BsXyQbZiQujHkdhwPwdol.");
+    }
+
+    #[test]
+    fn split_checks_the_shape() {
+        assert_eq!(split("$1$saltsalt$qjXMvbEw8oaL.CzflDtaK/"), Some(("saltsalt", "qjXMvbEw8oaL.CzflDtaK/")));
+        assert_eq!(split("$apr1$saltsalt$yAAkm4libquA.ZWLHbSBq/"), None);
+        assert_eq!(split("$1$saltsaltsalt$qjXMvbEw8oaL.CzflDtaK/"), None);
+        assert_eq!(split("$1$saltsalt$qjXMvbEw8oaL"), None);
+        assert_eq!(split("$1$saltsalt$qjXMvbEw8oaL.CzflDtaK!"), None);
+    }
+}
@@ -0,0 +1,233 @@
+use std::{
+    fs,
+    path::PathBuf,
+    sync::RwLock,
+    time::SystemTime,
+};
+
+use serde::{
+    Serialize,
+    Deserialize
+};
+
+use crate::{
+    error::Error,
+    htpasswd_database::HtpasswdDatabase,
+    load_options::LoadOptions,
+    password_hash::PasswordHash,
+};
+
+// What to do with a user found in several of the merged files
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub enum ConflictStrategy {
+    // Fail the merge with `DuplicateUser`
+    Fail,
+    // Keep the entry of the first file listing the user
+    FirstWins,
+    // Keep the entry of the last file listing the user
+    LastWins,
+}
+
+struct MergedState {
+    htpasswd_database: HtpasswdDatabase,
+    // Modification times of the files as of the last successful merge
+    modified: Vec<Option<SystemTime>>,
+}
+
+// Several Htpasswd files (e.g. one per team) merged into a single database,
+// and reloaded as a unit. When a `Data<MergedReloadableDatabase>` is added to
+// the actix app, `AuthControl` uses it in place of the
+// `Data<HtpasswdDatabase>`.
+//
+// Files are not watched by a background task: call `reload_if_changed`
+// periodically (e.g. from an `actix_rt::time::interval` loop) to pick up the
+// changes. If any file fails to load or the merge fails, the last good
+// merged database is kept, and the error is logged and returned.
+//
+// Changes are detected from the modification times of the files only. A
+// file rewritten twice within the mtime granularity of its filesystem (up
+// to a few seconds on some network or FAT filesystems), or replaced by a
+// copy keeping an older mtime (e.g. `cp -p`, `rsync -t`), may go unnoticed:
+// call `reload` to merge the files again regardless.
+pub struct MergedReloadableDatabase {
+    paths: Vec<PathBuf>,
+    options: LoadOptions,
+    conflict_strategy: ConflictStrategy,
+    state: RwLock<MergedState>,
+}
+impl MergedReloadableDatabase {
+    // The initial merge must succeed
+    pub fn new(
+        paths: Vec<PathBuf>,
+        options: LoadOptions,
+        conflict_strategy: ConflictStrategy
+    ) -> Result<MergedReloadableDatabase, Error> {
+        let modified = modification_times(&paths);
+        let htpasswd_database = merge(&paths, &options, conflict_strategy)?;
+        Ok(MergedReloadableDatabase {
+            paths,
+            options,
+            conflict_strategy,
+            state: RwLock::new(MergedState { htpasswd_database, modified }),
+        })
+    }
+
+    // Merge all the files again, whether they changed or not
+    pub fn reload(&self) -> Result<(), Error> {
+        let modified = modification_times(&self.paths);
+        self.reload_with(modified)
+    }
+
+    // Merge all the files again if any of them changed since the last
+    // successful merge (judging by their modification time, see the caveat
+    // above). Returns whether a merge happened.
+    pub fn reload_if_changed(&self) -> Result<bool, Error> {
+        let modified = modification_times(&self.paths);
+        if modified == self.state.read().unwrap().modified {
+            return Ok(false);
+        }
+        self.reload_with(modified).map(|_| true)
+    }
+
+    fn reload_with(&self, modified: Vec<Option<SystemTime>>) -> Result<(), Error> {
+        match merge(&self.paths, &self.options, self.conflict_strategy) {
+            Ok(htpasswd_database) => {
+                *self.state.write().unwrap() = MergedState { htpasswd_database, modified };
+                Ok(())
+            }
+            Err(error) => {
+                log::error!("Cannot reload merged Htpasswd files, keeping the previous state: {}", error);
+                Err(error)
+            }
+        }
+    }
+
+    // Copy of the current merged database
+    pub fn snapshot(&self) -> HtpasswdDatabase {
+        self.state.read().unwrap().htpasswd_database.clone()
+    }
+
+    pub(crate) fn get(&self, user: &str) -> Vec<PasswordHash> {
+        self.state.read().unwrap().htpasswd_database.get(user).to_vec()
+    }
+
+    pub(crate) fn dummy_hash(&self) -> Option<PasswordHash> {
+        self.state.read().unwrap().htpasswd_database.dummy_hash()
+    }
+
+    pub(crate) fn is_expired(&self, user: &str) -> bool {
+        self.state.read().unwrap().htpasswd_database.is_expired(user)
+    }
+}
+
+// A missing file gets `None`, so that its reappearance counts as a change
+fn modification_times(paths: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    paths.iter()
+        .map(|path| fs::metadata(path).and_then(|metadata| metadata.modified()).ok())
+        .collect()
+}
+
+fn merge(
+    paths: &[PathBuf],
+    options: &LoadOptions,
+    conflict_strategy: ConflictStrategy
+) -> Result<HtpasswdDatabase, Error> {
+    let mut merged = HtpasswdDatabase::new();
+    for path in paths {
+        merged.merge(HtpasswdDatabase::load(path, options)?, conflict_strategy)?;
+    }
+    Ok(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::File,
+        time::Duration,
+    };
+
+    use super::*;
+
+    // Unique per test, so that tests can run in parallel
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("actix_htpasswd-{}-{}", std::process::id(), name))
+    }
+
+    fn write_file(path: &PathBuf, entries: &[(&str, &str)], modified: SystemTime) {
+        let content: String = entries.iter()
+            .map(|(user, password)| format!("{}:{}\n", user, PasswordHash::sha1(password)))
+            .collect();
+        fs::write(path, content).unwrap();
+        // Set explicitly, as a rewrite within the mtime granularity of the
+        // filesystem would otherwise go unnoticed
+        File::options().write(true).open(path).unwrap().set_modified(modified).unwrap();
+    }
+
+    #[test]
+    fn a_change_in_one_of_the_files_is_picked_up() {
+        let (team_a, team_b) = (temp_path("reload-team-a"), temp_path("reload-team-b"));
+        let start = SystemTime::now() - Duration::from_secs(60);
+        write_file(&team_a, &[("alice", "a")], start);
+        write_file(&team_b, &[("bob", "b")], start);
+
+        let merged = MergedReloadableDatabase::new(
+            vec![team_a.clone(), team_b.clone()], LoadOptions::new(), ConflictStrategy::Fail
+        ).unwrap();
+        assert!(merged.snapshot().contains("alice"));
+        assert!(merged.snapshot().contains("bob"));
+        assert!(!merged.reload_if_changed().unwrap());
+
+        write_file(&team_b, &[("bob", "b"), ("carol", "c")], start + Duration::from_secs(1));
+        assert!(merged.reload_if_changed().unwrap());
+        assert!(merged.snapshot().contains("alice"));
+        assert_eq!(merged.get("carol"), vec![PasswordHash::sha1("c")]);
+        assert!(!merged.reload_if_changed().unwrap());
+
+        // A broken file keeps the last good merge
+        fs::write(&team_a, "not a valid line\n").unwrap();
+        File::options().write(true).open(&team_a).unwrap()
+            .set_modified(start + Duration::from_secs(2)).unwrap();
+        assert!(merged.reload_if_changed().is_err());
+        assert!(merged.snapshot().contains("alice"));
+
+        fs::remove_file(&team_a).unwrap();
+        fs::remove_file(&team_b).unwrap();
+    }
+
+    #[test]
+    fn conflicts_follow_the_order_of_the_files() {
+        let (first, last) = (temp_path("conflict-first"), temp_path("conflict-last"));
+        let start = SystemTime::now() - Duration::from_secs(60);
+        write_file(&first, &[("alice", "first-alice"), ("bob", "first-bob")], start);
+        write_file(&last, &[("alice", "last-alice"), ("carol", "last-carol")], start);
+        let paths = vec![first.clone(), last.clone()];
+
+        let merged = MergedReloadableDatabase::new(paths.clone(), LoadOptions::new(), ConflictStrategy::FirstWins)
+            .unwrap();
+        assert_eq!(merged.get("alice"), vec![PasswordHash::sha1("first-alice")]);
+        assert_eq!(merged.get("bob"), vec![PasswordHash::sha1("first-bob")]);
+        assert_eq!(merged.get("carol"), vec![PasswordHash::sha1("last-carol")]);
+
+        match MergedReloadableDatabase::new(paths.clone(), LoadOptions::new(), ConflictStrategy::Fail) {
+            Err(Error::DuplicateUser { user }) => assert_eq!(user, "alice"),
+            Err(error) => panic!("unexpected {:?}", error),
+            Ok(_) => panic!("duplicate users should fail the merge"),
+        }
+
+        let merged = MergedReloadableDatabase::new(paths, LoadOptions::new(), ConflictStrategy::LastWins)
+            .unwrap();
+        assert_eq!(merged.get("alice"), vec![PasswordHash::sha1("last-alice")]);
+        assert_eq!(merged.get("bob"), vec![PasswordHash::sha1("first-bob")]);
+        assert_eq!(merged.get("carol"), vec![PasswordHash::sha1("last-carol")]);
+
+        // The strategy still applies on reload
+        write_file(&last, &[("alice", "new-alice"), ("bob", "last-bob")], start + Duration::from_secs(1));
+        assert!(merged.reload_if_changed().unwrap());
+        assert_eq!(merged.get("alice"), vec![PasswordHash::sha1("new-alice")]);
+        assert_eq!(merged.get("bob"), vec![PasswordHash::sha1("last-bob")]);
+        assert!(merged.get("carol").is_empty());
+
+        fs::remove_file(&first).unwrap();
+        fs::remove_file(&last).unwrap();
+    }
+}
@@ -0,0 +1,641 @@
+use std::{
+    convert::TryFrom,
+    fmt::{
+        Debug,
+        Display,
+        Formatter,
+    },
+    str::FromStr,
+    sync::Arc,
+};
+
+use sha1::{
+    Digest,
+    Sha1
+};
+#[cfg(feature = "scrypt")]
+use scrypt::{
+    password_hash::{
+        rand_core::OsRng,
+        Output,
+        PasswordHash as PhcString,
+        PasswordHasher,
+        PasswordVerifier,
+        SaltString,
+    },
+    Params,
+    Scrypt,
+};
+use serde::{
+    Serialize,
+    Deserialize
+};
+use subtle::{
+    Choice,
+    ConditionallySelectable,
+    ConstantTimeEq
+};
+
+#[cfg(feature = "md5_crypt")]
+use crate::md5_crypt;
+use crate::{
+    custom_verifier::CustomVerifier,
+    error::Error,
+    load_options::LoadOptions,
+};
+
+// Size in bytes of a SHA-1 digest
+const SHA1_DIGEST_LENGTH: usize = 20;
+
+// Digest checked against when the user is unknown, so that a lookup miss costs
+// the same as a wrong password. The result is masked out in that case anyway.
+const DUMMY_SHA1_DIGEST: [u8; SHA1_DIGEST_LENGTH] = [0; SHA1_DIGEST_LENGTH];
+
+// Ceilings on the cost of the scrypt hashes accepted when parsing, so that a
+// tampered or mistyped entry (e.g. "ln=40") cannot make a single verification
+// allocate more memory than the process can get, or run for minutes. A
+// verification needs 128 * r * 2^ln bytes: the parameters of `hash_password`
+// (ln=17, r=8, p=1) take 128 MiB, half of the ceiling.
+#[cfg(feature = "scrypt")]
+const MAX_SCRYPT_MEMORY: u128 = 256 * 1024 * 1024;
+#[cfg(feature = "scrypt")]
+const MAX_SCRYPT_P: u32 = 16;
+
+// Salt of the dummy scrypt hashes, see `PasswordHash::dummy`
+#[cfg(feature = "scrypt")]
+const DUMMY_SCRYPT_SALT: &str = "AAAAAAAAAAAAAAAAAAAAAA";
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub enum HashScheme {
+    Sha1,
+    Scrypt,
+    Md5Crypt,
+    Custom,
+}
+
+impl HashScheme {
+    // Whether the scheme is too fast to compute to withstand an offline
+    // attack on a leaked file. The strength of custom schemes is unknown to
+    // the crate, so they are not reported as weak.
+    pub fn is_weak(&self) -> bool {
+        match self {
+            HashScheme::Sha1 | HashScheme::Md5Crypt => true,
+            HashScheme::Scrypt | HashScheme::Custom => false,
+        }
+    }
+}
+
+impl Display for HashScheme {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            HashScheme::Sha1 => write!(f, "SHA-1"),
+            HashScheme::Scrypt => write!(f, "scrypt"),
+            HashScheme::Md5Crypt => write!(f, "MD5-crypt"),
+            HashScheme::Custom => write!(f, "custom"),
+        }
+    }
+}
+
+// Serialized as its Htpasswd string representation. Only the built-in schemes
+// can be deserialized, as custom verifiers are not known at that point.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum PasswordHash {
+    Sha1(Vec<u8>),
+    // Full PHC string, e.g. "$scrypt$ln=15,r=8,p=1$<salt>$<hash>"
+    #[cfg(feature = "scrypt")]
+    Scrypt(String),
+    // Full crypt string, e.g. "$1$<salt>$<hash>"
+    #[cfg(feature = "md5_crypt")]
+    Md5Crypt(String),
+    Custom {
+        verifier: Arc<dyn CustomVerifier>,
+        stored: String,
+    },
+}
+
+// Prefixes of well-known hash schemes which this crate cannot verify, so that
+// they can be reported as such instead of as garbage
+const UNSUPPORTED_SCHEME_PREFIXES: &[(&str, &str)] = &[
+    ("$2y$", "bcrypt"),
+    ("$2a$", "bcrypt"),
+    ("$2b$", "bcrypt"),
+    ("$argon2", "argon2"),
+    ("$apr1$", "apr1 (Apache MD5)"),
+    #[cfg(not(feature = "md5_crypt"))]
+    ("$1$", "MD5-crypt"),
+    ("$5$", "SHA-256-crypt"),
+    ("$6$", "SHA-512-crypt"),
+    ("$7$", "scrypt ($7$ format)"),
+    #[cfg(not(feature = "scrypt"))]
+    ("$scrypt$", "scrypt"),
+];
+
+#[derive(Debug)]
+pub(crate) enum ParseFailure {
+    UnknownScheme,
+    UnsupportedScheme(&'static str),
+    InvalidEncoding,
+    InvalidLength {
+        expected: usize,
+        got: usize,
+    },
+    #[cfg(feature = "scrypt")]
+    CostTooHigh {
+        log_n: u8,
+        r: u32,
+        p: u32,
+    },
+}
+impl ParseFailure {
+    // Same failure, for a hash which doesn't come from a file line
+    pub(crate) fn for_user(self, user: &str) -> Error {
+        match self {
+            ParseFailure::InvalidLength { expected, got } => Error::InvalidDigestLength {
+                user: user.to_owned(),
+                expected,
+                got,
+            },
+            parse_failure => Error::InvalidUserHash {
+                user: user.to_owned(),
+                reason: parse_failure.to_string(),
+            },
+        }
+    }
+}
+
+// Never mentions the hash itself, which should stay out of the logs
+impl Display for ParseFailure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            ParseFailure::UnknownScheme => write!(f, "unrecognized hash scheme"),
+            ParseFailure::UnsupportedScheme(scheme) => write!(f, "unsupported {} hash scheme", scheme),
+            ParseFailure::InvalidEncoding => write!(f, "invalid hash encoding"),
+            ParseFailure::InvalidLength { expected, got } => write!(
+                f, "invalid hash length: expected {} bytes, got {}",
+                expected, got
+            ),
+            #[cfg(feature = "scrypt")]
+            ParseFailure::CostTooHigh { log_n, r, p } => write!(
+                f, "scrypt parameters ln={}, r={}, p={} exceed the ceiling of {} MiB of memory and p={}",
+                log_n, r, p, MAX_SCRYPT_MEMORY / (1024 * 1024), MAX_SCRYPT_P
+            ),
+        }
+    }
+}
+impl PasswordHash {
+    pub fn sha1(password: &str) -> PasswordHash {
+        let mut sha1_hasher = Sha1::new();
+        sha1_hasher.input(password);
+        PasswordHash::Sha1(sha1_hasher.result().to_vec())
+    }
+
+    pub fn scheme(&self) -> HashScheme {
+        match self {
+            PasswordHash::Sha1(_) => HashScheme::Sha1,
+            #[cfg(feature = "scrypt")]
+            PasswordHash::Scrypt(_) => HashScheme::Scrypt,
+            #[cfg(feature = "md5_crypt")]
+            PasswordHash::Md5Crypt(_) => HashScheme::Md5Crypt,
+            PasswordHash::Custom { .. } => HashScheme::Custom,
+        }
+    }
+
+    // Whether verifying a password against this hash is CPU-heavy, and should
+    // thus be kept off the async runtime threads
+    pub(crate) fn is_expensive(&self) -> bool {
+        match self {
+            PasswordHash::Sha1(_) => false,
+            #[cfg(feature = "scrypt")]
+            PasswordHash::Scrypt(_) => true,
+            #[cfg(feature = "md5_crypt")]
+            PasswordHash::Md5Crypt(_) => false,
+            PasswordHash::Custom { .. } => false,
+        }
+    }
+
+    // Recognize the scheme of a hash string from its prefix. Custom verifiers
+    // are tried first, so that they can handle any prefix they like.
+    pub(crate) fn parse(
+        hash_string: &str,
+        options: &LoadOptions
+    ) -> Result<PasswordHash, ParseFailure> {
+        let custom_verifier = options.custom_verifiers.iter()
+            .find(|verifier| hash_string.starts_with(verifier.prefix()));
+        if let Some(verifier) = custom_verifier {
+            return Ok(PasswordHash::Custom {
+                verifier: verifier.clone(),
+                stored: hash_string.to_owned(),
+            });
+        }
+
+        if let Some(base64_sha1_password) = hash_string.strip_prefix("{SHA}") {
+            let sha1_password = base64::decode(base64_sha1_password)
+                .map_err(|_| ParseFailure::InvalidEncoding)?;
+            // Valid base64, but not a SHA-1 digest: most likely a truncated
+            // or corrupted line
+            if sha1_password.len() != SHA1_DIGEST_LENGTH {
+                return Err(ParseFailure::InvalidLength {
+                    expected: SHA1_DIGEST_LENGTH,
+                    got: sha1_password.len(),
+                });
+            }
+            return Ok(PasswordHash::Sha1(sha1_password));
+        }
+
+        // Only the PHC format is supported for scrypt, not the "$7$" one
+        #[cfg(feature = "scrypt")]
+        {
+            if hash_string.starts_with("$scrypt$") {
+                let phc_string = PhcString::new(hash_string)
+                    .map_err(|_| ParseFailure::InvalidEncoding)?;
+                // No password could ever match a hash-less string
+                if phc_string.hash.is_none() {
+                    return Err(ParseFailure::InvalidEncoding);
+                }
+                let params = Params::try_from(&phc_string)
+                    .map_err(|_| ParseFailure::InvalidEncoding)?;
+                // Cannot overflow, as r < 2^32 and ln < 64
+                let memory = (128 * u128::from(params.r())) << params.log_n();
+                if memory > MAX_SCRYPT_MEMORY || params.p() > MAX_SCRYPT_P {
+                    return Err(ParseFailure::CostTooHigh {
+                        log_n: params.log_n(),
+                        r: params.r(),
+                        p: params.p(),
+                    });
+                }
+                return Ok(PasswordHash::Scrypt(hash_string.to_owned()));
+            }
+        }
+
+        // Not to be mistaken for "$apr1$", the Apache variant
+        #[cfg(feature = "md5_crypt")]
+        {
+            if hash_string.starts_with(md5_crypt::MAGIC) {
+                return md5_crypt::split(hash_string)
+                    .map(|_| PasswordHash::Md5Crypt(hash_string.to_owned()))
+                    .ok_or(ParseFailure::InvalidEncoding);
+            }
+        }
+
+        match UNSUPPORTED_SCHEME_PREFIXES.iter()
+            .find(|(prefix, _)| hash_string.starts_with(prefix)) {
+            Some((_, scheme)) => Err(ParseFailure::UnsupportedScheme(scheme)),
+            None => Err(ParseFailure::UnknownScheme),
+        }
+    }
+
+    // Check that the stored value is well-formed for its scheme. Entries
+    // built through `add` or parsed are always fine, but a hash built by hand
+    // might carry a digest of the wrong size, or an unusable string.
+    pub(crate) fn validate(&self, user: &str) -> Result<(), Error> {
+        match self {
+            PasswordHash::Sha1(sha1_password) => {
+                if sha1_password.len() != SHA1_DIGEST_LENGTH {
+                    return Err(Error::InvalidDigestLength {
+                        user: user.to_owned(),
+                        expected: SHA1_DIGEST_LENGTH,
+                        got: sha1_password.len(),
+                    });
+                }
+            }
+            // Parsed again, with the same checks as when loading
+            #[cfg(feature = "scrypt")]
+            PasswordHash::Scrypt(phc_string) => {
+                PasswordHash::parse(phc_string, &LoadOptions::default())
+                    .map_err(|parse_failure| parse_failure.for_user(user))?;
+            }
+            #[cfg(feature = "md5_crypt")]
+            PasswordHash::Md5Crypt(crypt_string) => {
+                PasswordHash::parse(crypt_string, &LoadOptions::default())
+                    .map_err(|parse_failure| parse_failure.for_user(user))?;
+            }
+            // Custom hash strings are opaque to the crate
+            PasswordHash::Custom { .. } => {}
+        }
+        Ok(())
+    }
+
+    // Hash of the same scheme and cost as this one, which no password
+    // matches, for unknown users to cost as much to verify as known ones.
+    // Only expensive schemes get one: the dummy SHA-1 digest covers the
+    // others.
+    pub(crate) fn dummy(&self) -> Option<PasswordHash> {
+        match self {
+            #[cfg(feature = "scrypt")]
+            PasswordHash::Scrypt(phc_string) => {
+                let phc_string = PhcString::new(phc_string).ok()?;
+                let output_length = phc_string.hash.map(|output| output.len())
+                    .unwrap_or(Params::RECOMMENDED_LEN);
+                let output = Output::new(&vec![0; output_length]).ok()?;
+                Some(PasswordHash::Scrypt(format!(
+                    "$scrypt${}${}${}",
+                    phc_string.params, DUMMY_SCRYPT_SALT, output
+                )))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Display for PasswordHash {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            PasswordHash::Sha1(sha1_password) => write!(
+                f, "{{SHA}}{}", base64::encode(sha1_password)
+            ),
+            #[cfg(feature = "scrypt")]
+            PasswordHash::Scrypt(phc_string) => write!(f, "{}", phc_string),
+            #[cfg(feature = "md5_crypt")]
+            PasswordHash::Md5Crypt(crypt_string) => write!(f, "{}", crypt_string),
+            PasswordHash::Custom { stored, .. } => write!(f, "{}", stored),
+        }
+    }
+}
+
+impl Debug for PasswordHash {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            PasswordHash::Sha1(sha1_password) => f.debug_tuple("Sha1")
+                .field(sha1_password)
+                .finish(),
+            #[cfg(feature = "scrypt")]
+            PasswordHash::Scrypt(phc_string) => f.debug_tuple("Scrypt")
+                .field(phc_string)
+                .finish(),
+            #[cfg(feature = "md5_crypt")]
+            PasswordHash::Md5Crypt(crypt_string) => f.debug_tuple("Md5Crypt")
+                .field(crypt_string)
+                .finish(),
+            PasswordHash::Custom { stored, .. } => f.debug_struct("Custom")
+                .field("stored", stored)
+                .finish(),
+        }
+    }
+}
+
+impl PartialEq for PasswordHash {
+    fn eq(&self, other: &PasswordHash) -> bool {
+        match (self, other) {
+            (PasswordHash::Sha1(a), PasswordHash::Sha1(b)) => a == b,
+            #[cfg(feature = "scrypt")]
+            (PasswordHash::Scrypt(a), PasswordHash::Scrypt(b)) => a == b,
+            #[cfg(feature = "md5_crypt")]
+            (PasswordHash::Md5Crypt(a), PasswordHash::Md5Crypt(b)) => a == b,
+            (
+                PasswordHash::Custom { verifier: verifier_a, stored: a },
+                PasswordHash::Custom { verifier: verifier_b, stored: b },
+            ) => verifier_a.prefix() == verifier_b.prefix() && a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for PasswordHash {}
+
+impl FromStr for PasswordHash {
+    type Err = Error;
+
+    fn from_str(hash_string: &str) -> Result<Self, Self::Err> {
+        PasswordHash::parse(hash_string, &LoadOptions::default())
+            .map_err(|parse_failure| Error::InvalidHashString {
+                reason: parse_failure.to_string(),
+            })
+    }
+}
+
+impl TryFrom<String> for PasswordHash {
+    type Error = Error;
+
+    fn try_from(hash_string: String) -> Result<Self, Self::Error> {
+        hash_string.parse()
+    }
+}
+
+impl From<PasswordHash> for String {
+    fn from(password_hash: PasswordHash) -> String {
+        password_hash.to_string()
+    }
+}
+
+// Hash a plaintext password with the given scheme, e.g. to provision users.
+// scrypt hashes get a random salt and the default parameters of the scrypt
+// crate. Custom schemes cannot be produced: their verifiers only verify.
+pub fn hash_password(password: &str, scheme: HashScheme) -> Result<PasswordHash, Error> {
+    let cannot_hash = |reason: &str| Error::CannotHashPassword {
+        scheme: scheme.to_string(),
+        reason: reason.to_owned(),
+    };
+
+    match scheme {
+        HashScheme::Sha1 => Ok(PasswordHash::sha1(password)),
+        #[cfg(feature = "scrypt")]
+        HashScheme::Scrypt => {
+            let salt = SaltString::generate(&mut OsRng);
+            Scrypt.hash_password(password.as_bytes(), &salt)
+                .map(|phc_string| PasswordHash::Scrypt(phc_string.to_string()))
+                .map_err(|hash_error| cannot_hash(&hash_error.to_string()))
+        }
+        #[cfg(not(feature = "scrypt"))]
+        HashScheme::Scrypt => Err(cannot_hash("the \"scrypt\" feature is disabled")),
+        HashScheme::Md5Crypt => Err(cannot_hash("this weak scheme is only supported for verification")),
+        HashScheme::Custom => Err(cannot_hash("custom verifiers cannot hash passwords")),
+    }
+}
+
+// Verify the supplied password against the stored hash if any, or against a
+// fixed dummy digest otherwise. The real or dummy digest is picked with a
+// constant-time selection, and the comparison always runs, so that the code
+// path doesn't depend on whether the user exists.
+pub(crate) fn verify_password(stored: Option<&PasswordHash>, password: &str) -> bool {
+    match stored {
+        Some(PasswordHash::Custom { verifier, stored }) => {
+            return verifier.verify(stored, password);
+        }
+        // The scrypt implementation takes care of the constant-time
+        // comparison of the derived key
+        #[cfg(feature = "scrypt")]
+        Some(PasswordHash::Scrypt(phc_string)) => {
+            return PhcString::new(phc_string)
+                .map(|phc_string| {
+                    Scrypt.verify_password(password.as_bytes(), &phc_string).is_ok()
+                })
+                .unwrap_or(false);
+        }
+        #[cfg(feature = "md5_crypt")]
+        Some(PasswordHash::Md5Crypt(crypt_string)) => {
+            return md5_crypt::split(crypt_string)
+                .map(|(salt, encoded_hash)| {
+                    let computed = md5_crypt::encoded_hash(password.as_bytes(), salt.as_bytes());
+                    computed.as_bytes().ct_eq(encoded_hash.as_bytes()).into()
+                })
+                .unwrap_or(false);
+        }
+        _ => {}
+    }
+
+    let mut stored_digest = [0; SHA1_DIGEST_LENGTH];
+    let stored_is_valid = match stored {
+        Some(PasswordHash::Sha1(sha1_password))
+            if sha1_password.len() == SHA1_DIGEST_LENGTH => {
+            stored_digest.copy_from_slice(sha1_password);
+            Choice::from(1)
+        }
+        _ => Choice::from(0),
+    };
+
+    let mut expected_digest = [0; SHA1_DIGEST_LENGTH];
+    for (i, byte) in expected_digest.iter_mut().enumerate() {
+        *byte = u8::conditional_select(
+            &DUMMY_SHA1_DIGEST[i], &stored_digest[i], stored_is_valid
+        );
+    }
+
+    let mut sha1_hasher = Sha1::new();
+    sha1_hasher.input(password);
+    let sha1_password = sha1_hasher.result();
+
+    (sha1_password.as_slice().ct_eq(&expected_digest) & stored_is_valid).into()
+}
+
+// Verify the supplied password against all the hashes stored for a user, and
+// succeed if any of them matches. Every hash is checked, without stopping at
+// the first match, so that the timing doesn't tell which one matched. With no
+// stored hash at all, the given dummy hash (see `PasswordHash::dummy`) is
+// checked instead if any, or else the dummy digest, and the result discarded.
+pub(crate) fn verify_any(stored: &[PasswordHash], dummy: Option<&PasswordHash>, password: &str) -> bool {
+    if stored.is_empty() {
+        verify_password(dummy, password);
+        return false;
+    }
+
+    let mut matches = Choice::from(0);
+    for password_hash in stored {
+        matches |= Choice::from(verify_password(Some(password_hash), password) as u8);
+    }
+    matches.into()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // Same as `hash_password`, with a cost low enough for tests
+    #[cfg(feature = "scrypt")]
+    pub(crate) fn cheap_scrypt_hash(password: &str) -> PasswordHash {
+        let salt = SaltString::generate(&mut OsRng);
+        let params = Params::new(4, 8, 1, Params::RECOMMENDED_LEN).unwrap();
+        let phc_string = Scrypt.hash_password_customized(password.as_bytes(), None, None, params, &salt)
+            .unwrap();
+        PasswordHash::Scrypt(phc_string.to_string())
+    }
+
+    #[test]
+    fn verify_password_uses_the_stored_digest_when_there_is_one() {
+        let stored = PasswordHash::sha1("secret");
+        assert!(verify_password(Some(&stored), "secret"));
+        assert!(!verify_password(Some(&stored), "wrong"));
+    }
+
+    #[test]
+    fn verify_password_never_matches_the_dummy_digest() {
+        assert!(!verify_password(None, "secret"));
+        assert!(!verify_password(None, ""));
+        // A stored digest of the wrong size falls back to the dummy one
+        assert!(!verify_password(Some(&PasswordHash::Sha1(vec![0; SHA1_DIGEST_LENGTH - 1])), "secret"));
+    }
+
+    #[test]
+    fn verify_any_matches_any_of_the_stored_hashes() {
+        let stored = vec![PasswordHash::sha1("old"), PasswordHash::sha1("new")];
+        assert!(verify_any(&stored, None, "old"));
+        assert!(verify_any(&stored, None, "new"));
+        assert!(!verify_any(&stored, None, "other"));
+        assert!(!verify_any(&[], None, "old"));
+    }
+
+    #[cfg(feature = "scrypt")]
+    fn scrypt_hash_string(params: &str) -> String {
+        format!("$scrypt${}${}${}", params, DUMMY_SCRYPT_SALT, "A".repeat(43))
+    }
+
+    #[cfg(feature = "scrypt")]
+    #[test]
+    fn scrypt_hashes_over_the_cost_ceiling_are_rejected() {
+        let parse = |params: &str| PasswordHash::parse(&scrypt_hash_string(params), &LoadOptions::default());
+
+        assert!(parse("ln=17,r=8,p=1").is_ok());
+        assert!(parse("ln=18,r=8,p=1").is_ok());
+        assert!(parse("ln=1,r=8,p=16").is_ok());
+        for params in &["ln=40,r=8,p=1", "ln=19,r=8,p=1", "ln=17,r=32,p=1", "ln=17,r=8,p=17"] {
+            match parse(params) {
+                Err(ParseFailure::CostTooHigh { .. }) => {}
+                _ => panic!("{} should be too costly", params),
+            }
+        }
+    }
+
+    #[cfg(feature = "scrypt")]
+    #[test]
+    fn validate_parses_scrypt_hashes_again() {
+        assert!(cheap_scrypt_hash("secret").validate("alice").is_ok());
+
+        let too_costly = PasswordHash::Scrypt(scrypt_hash_string("ln=40,r=8,p=1"));
+        match too_costly.validate("alice") {
+            Err(Error::InvalidUserHash { user, .. }) => assert_eq!(user, "alice"),
+            other => panic!("unexpected {:?}", other),
+        }
+        let garbage = PasswordHash::Scrypt("$scrypt$garbage".to_owned());
+        assert!(garbage.validate("alice").is_err());
+    }
+
+    // scrypt("password", "NaCl", N = 1024, r = 8, p = 16), from RFC 7914
+    #[cfg(feature = "scrypt")]
+    const RFC_7914_SCRYPT_HASH: &str = "$scrypt$ln=10,r=8,p=16$TmFDbA$\
+        /bq+HJ00cgB4VucZDQHp/nxq18vII3gw53N2Y0s3MWIurzDZLiKjiG/xCSedmDDaxyevuUqD7m2DYMvfoswGQA";
+
+    #[cfg(feature = "scrypt")]
+    #[test]
+    fn scrypt_hashes_are_verified_against_a_known_vector() {
+        let stored = PasswordHash::parse(RFC_7914_SCRYPT_HASH, &LoadOptions::default()).unwrap();
+        assert_eq!(stored.scheme(), HashScheme::Scrypt);
+        // A single verification, as this cost is slow in debug builds
+        assert!(verify_password(Some(&stored), "password"));
+    }
+
+    #[cfg(feature = "scrypt")]
+    #[test]
+    fn scrypt_dummy_keeps_the_cost_and_matches_no_password() {
+        let stored = cheap_scrypt_hash("secret");
+        let dummy = stored.dummy().unwrap();
+        assert!(dummy.to_string().starts_with("$scrypt$ln=4,r=8,p=1$"));
+        assert!(dummy.validate("alice").is_ok());
+        assert!(verify_password(Some(&stored), "secret"));
+        assert!(!verify_password(Some(&dummy), "secret"));
+        assert!(!verify_any(&[], Some(&dummy), "secret"));
+
+        assert!(PasswordHash::sha1("secret").dummy().is_none());
+    }
+
+    #[cfg(feature = "md5_crypt")]
+    #[test]
+    fn md5_crypt_is_verified_and_not_mistaken_for_apr1() {
+        let parse = |hash_string: &str| PasswordHash::parse(hash_string, &LoadOptions::default());
+
+        let md5_crypt = parse("$1$saltsalt$qjXMvbEw8oaL.CzflDtaK/").unwrap();
+        assert_eq!(md5_crypt.scheme(), HashScheme::Md5Crypt);
+        assert!(verify_password(Some(&md5_crypt), "password"));
+        assert!(!verify_password(Some(&md5_crypt), "Password"));
+
+        let long_password = parse("$1$saltsalt$BsXyQbZiQujHkdhwPwdol.").unwrap();
+        assert!(verify_password(Some(&long_password), "correct horse battery staple"));
+
+        // Same salt and password, but the Apache variant
+        match parse("$apr1$saltsalt$yAAkm4libquA.ZWLHbSBq/") {
+            Err(ParseFailure::UnsupportedScheme(scheme)) => assert!(scheme.starts_with("apr1")),
+            _ => panic!("$apr1$ should be unsupported"),
+        }
+        let apr1_hash_as_md5_crypt = parse("$1$saltsalt$yAAkm4libquA.ZWLHbSBq/").unwrap();
+        assert!(!verify_password(Some(&apr1_hash_as_md5_crypt), "password"));
+    }
+}
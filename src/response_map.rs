@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+
+use actix_web::http::StatusCode;
+use serde::{
+    Serialize,
+    Deserialize
+};
+
+// The distinct reasons for which AuthControl can reject a request
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub enum FailureKind {
+    UnknownUser,
+    WrongPassword,
+    MalformedHeader,
+    EmptyPassword,
+    InsecureTransport,
+    PasswordExpired,
+    Throttled,
+    // Too many expensive verifications running, see `VerificationLimiter`
+    Overloaded,
+    // Anonymous request denied by the user control policy
+    AuthenticationRequired,
+    // Logged user denied by the user control policy
+    Forbidden,
+}
+impl FailureKind {
+    pub(crate) fn default_status(&self) -> StatusCode {
+        match self {
+            // Not a 401, so that browsers don't prompt for credentials again
+            // over the same insecure connection
+            FailureKind::InsecureTransport => StatusCode::FORBIDDEN,
+            // Prompting again is pointless, the same password stays expired
+            FailureKind::PasswordExpired => StatusCode::FORBIDDEN,
+            FailureKind::Throttled => StatusCode::TOO_MANY_REQUESTS,
+            FailureKind::Overloaded => StatusCode::SERVICE_UNAVAILABLE,
+            FailureKind::Forbidden => StatusCode::FORBIDDEN,
+            _ => StatusCode::UNAUTHORIZED,
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub(crate) struct MappedResponse {
+    pub(crate) status: Option<StatusCode>,
+    pub(crate) body: Option<String>,
+}
+
+// Overrides of the status code and body of the responses sent by
+// AuthControl, for each kind of failure. Add a `Data<ResponseMap>` to the
+// actix app to use it (a bare `ResponseMap` is accepted too);
+// the kinds without override keep the default response (401, or 403 for
+// `Forbidden`, `InsecureTransport` and `PasswordExpired`, 429 for
+// `Throttled`, or 503 for `Overloaded`, with the message of the error as
+// body; a 401 becomes a 403 when there is no challenge to send along). For instance,
+// `ResponseMap::new().status(FailureKind::MalformedHeader, StatusCode::BAD_REQUEST)`
+// answers malformed Authorization headers with a 400.
+//
+// By default, `UnknownUser` and `WrongPassword` get the same response, so
+// that clients cannot tell whether a user exists. Mapping them differently
+// gives that information away.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct ResponseMap {
+    mapped_responses: HashMap<FailureKind, MappedResponse>,
+}
+impl ResponseMap {
+    pub fn new() -> ResponseMap {
+        ResponseMap::default()
+    }
+
+    // The WWW-Authenticate challenge is only sent along with 401 responses
+    pub fn status(mut self, kind: FailureKind, status: StatusCode) -> ResponseMap {
+        self.mapped_responses.entry(kind).or_default().status = Some(status);
+        self
+    }
+
+    pub fn body(mut self, kind: FailureKind, body: &str) -> ResponseMap {
+        self.mapped_responses.entry(kind).or_default().body = Some(body.to_owned());
+        self
+    }
+
+    pub(crate) fn get(&self, kind: FailureKind) -> Option<&MappedResponse> {
+        self.mapped_responses.get(&kind)
+    }
+}
@@ -1,3 +1,8 @@
+use actix_web::{
+    HttpRequest,
+    dev::RequestHead,
+    http::Method,
+};
 use serde::{
     Serialize,
     Deserialize
@@ -9,6 +14,33 @@ pub trait UserControlPolicy {
     fn display() -> &'static str;
 
     fn allows(auth_result: &AuthResult) -> bool;
+
+    // Same as `allows`, with the head of the request at hand, so that a
+    // policy can branch on its method, path or headers. Both `AuthControl`
+    // and the `HtpasswdGuard` end up calling this one; by default, it simply
+    // defers to `allows`. Where no request is available at all
+    // (`AuthControl::from_header_value`), `allows` is used instead, so it
+    // should give the most restrictive answer.
+    fn allows_head(auth_result: &AuthResult, _head: &RequestHead) -> bool {
+        Self::allows(auth_result)
+    }
+
+    // Same as `allows_head`, with the full request, e.g. to look at the app
+    // data or the match info. `AuthControl` calls this one; by default, it
+    // defers to `allows_head`. The `HtpasswdGuard` only sees the request
+    // head, so a policy overriding this one should keep `allows_head`
+    // consistent with it.
+    fn allows_request(auth_result: &AuthResult, req: &HttpRequest) -> bool {
+        Self::allows_head(auth_result, req.head())
+    }
+
+    // Realm advertised in the 401 challenge of the routes guarded by this
+    // policy, e.g. "Admin" for an admin-only policy. When a policy provides a
+    // realm, it takes precedence over the one of the BasicAuthConfig; by
+    // default (None), the BasicAuthConfig realm is used.
+    fn realm() -> Option<&'static str> {
+        None
+    }
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
@@ -36,3 +68,48 @@ impl UserControlPolicy for AnyLoggedUser {
         }
     }
 }
+
+// Safe methods (GET, HEAD, OPTIONS) are open to anyone, while the others
+// (POST, PUT, DELETE...) require a logged user. Without a request to look at,
+// only logged users are allowed.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct ReadAnyoneWriteLoggedIn;
+impl UserControlPolicy for ReadAnyoneWriteLoggedIn {
+    fn display() -> &'static str {
+        "ReadAnyoneWriteLoggedIn"
+    }
+
+    fn allows(auth_result: &AuthResult) -> bool {
+        AnyLoggedUser::allows(auth_result)
+    }
+
+    fn allows_head(auth_result: &AuthResult, head: &RequestHead) -> bool {
+        match head.method {
+            Method::GET | Method::HEAD | Method::OPTIONS => true,
+            _ => AnyLoggedUser::allows(auth_result),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::*;
+
+    #[test]
+    fn read_anyone_write_logged_in_branches_on_the_method() {
+        let anonymous = AuthResult::Anonymous;
+        let logged_user = AuthResult::LoggedUser { user: "alice".to_owned() };
+        let get = TestRequest::get().to_http_request();
+        let post = TestRequest::post().to_http_request();
+
+        assert!(ReadAnyoneWriteLoggedIn::allows_request(&anonymous, &get));
+        assert!(ReadAnyoneWriteLoggedIn::allows_head(&anonymous, get.head()));
+        assert!(!ReadAnyoneWriteLoggedIn::allows_request(&anonymous, &post));
+        assert!(!ReadAnyoneWriteLoggedIn::allows_head(&anonymous, post.head()));
+        assert!(ReadAnyoneWriteLoggedIn::allows_request(&logged_user, &post));
+        // Most restrictive answer without a request
+        assert!(!ReadAnyoneWriteLoggedIn::allows(&anonymous));
+    }
+}
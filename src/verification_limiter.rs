@@ -0,0 +1,85 @@
+use std::sync::atomic::{
+    AtomicUsize,
+    Ordering,
+};
+
+use actix_web::web::Data;
+
+// Caps the number of expensive verifications (scrypt) running at the same
+// time on the blocking thread pool, so that a burst of logins cannot queue
+// up an unbounded amount of CPU work. Opt-in: add a
+// `Data<VerificationLimiter>` to the actix app. Requests over the cap are
+// rejected with a 503 (`FailureKind::Overloaded`) without being verified,
+// and can be retried later.
+//
+// Only the verifications done by `AuthControl` count: the `HtpasswdGuard`
+// and `AuthControl::from_header_value` verify on the calling thread.
+#[derive(Debug)]
+pub struct VerificationLimiter {
+    max_concurrent: usize,
+    running: AtomicUsize,
+}
+impl VerificationLimiter {
+    pub fn new(max_concurrent: usize) -> VerificationLimiter {
+        VerificationLimiter {
+            max_concurrent,
+            running: AtomicUsize::new(0),
+        }
+    }
+
+    // Reserve a slot for one verification, released when the permit is
+    // dropped, or tell that all the slots are taken
+    pub(crate) fn try_acquire(limiter: &Data<VerificationLimiter>) -> Option<VerificationPermit> {
+        limiter.running
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |running| {
+                if running < limiter.max_concurrent {
+                    Some(running + 1)
+                } else {
+                    None
+                }
+            })
+            .ok()
+            .map(|_| VerificationPermit { limiter: limiter.clone() })
+    }
+}
+
+// Moved along with the verification to the blocking thread pool, so that the
+// slot stays taken until the verification is actually over, even if the
+// request is dropped in the meantime
+pub(crate) struct VerificationPermit {
+    limiter: Data<VerificationLimiter>,
+}
+impl Drop for VerificationPermit {
+    fn drop(&mut self) {
+        self.limiter.running.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn permits_are_capped_and_released_on_drop() {
+        let limiter = Data::new(VerificationLimiter::new(2));
+
+        let first = VerificationLimiter::try_acquire(&limiter).unwrap();
+        let second = VerificationLimiter::try_acquire(&limiter).unwrap();
+        assert!(VerificationLimiter::try_acquire(&limiter).is_none());
+
+        drop(first);
+        let third = VerificationLimiter::try_acquire(&limiter);
+        assert!(third.is_some());
+        assert!(VerificationLimiter::try_acquire(&limiter).is_none());
+
+        drop(second);
+        drop(third);
+        assert!(VerificationLimiter::try_acquire(&limiter).is_some());
+    }
+
+    #[test]
+    fn zero_rejects_every_verification() {
+        let limiter = Data::new(VerificationLimiter::new(0));
+        assert!(VerificationLimiter::try_acquire(&limiter).is_none());
+    }
+}